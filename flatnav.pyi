@@ -0,0 +1,238 @@
+import sys
+from os import PathLike
+from typing import Any, Callable, Generic, Literal, Optional, Sequence, TypeVar, Union
+
+if sys.version_info >= (3, 11):
+    from typing import Self
+else:
+    from typing_extensions import Self
+
+import numpy as np
+import numpy.typing as npt
+
+_D = TypeVar("_D", np.float32, np.uint8, np.int8)
+
+_Metric = Literal["l2", "euclidean", "ip", "inner_product"]
+_DType = Union[Literal["float32", "uint8", "int8"], type, np.dtype]
+_AttributeValue = Union[int, str]
+_Attributes = dict[str, Union[_AttributeValue, list[_AttributeValue]]]
+
+class Reordering: ...
+
+class GOrder(Reordering):
+    def __init__(self, w: int) -> None: ...
+
+class ReverseCuthillMckee(Reordering):
+    def __init__(self) -> None: ...
+
+class BfsOrder(Reordering):
+    def __init__(self, root: int = 0) -> None: ...
+
+class DegreeSort(Reordering):
+    def __init__(self) -> None: ...
+
+class HubCluster(Reordering):
+    def __init__(self) -> None: ...
+
+class RandomOrder(Reordering):
+    def __init__(self, seed: int = 0) -> None: ...
+
+class ReorderingMetrics:
+    avg_gap: float
+    avg_log_gap: float
+    bandwidth: int
+    gorder_score: int
+
+class NamespaceStats:
+    len: int
+    edges: int
+    intra_edges: int
+
+class GraphStats:
+    n_nodes: int
+    n_edges: int
+    out_degrees: list[int]
+    in_degrees: list[int]
+    n_components: int
+    n_unreachable: int
+    avg_edge_length: float
+
+class Filter:
+    @staticmethod
+    def eq(name: str, value: _AttributeValue) -> Filter: ...
+    @staticmethod
+    def is_in(name: str, values: list[_AttributeValue]) -> Filter: ...
+    @staticmethod
+    def range(name: str, min: int = ..., max: int = ...) -> Filter: ...
+    def __and__(self, other: Filter) -> Filter: ...
+    def __or__(self, other: Filter) -> Filter: ...
+    def __invert__(self) -> Filter: ...
+
+# Methods shared by every index class. These base classes only exist in this
+# stub, the concrete classes below are defined by the extension module.
+class _VectorIndex(Generic[_D]):
+    def insert(
+        self,
+        label: int,
+        data: npt.NDArray[_D],
+        ef_construction: int,
+        payload: Optional[bytes] = None,
+        attributes: Optional[_Attributes] = None,
+        namespace: int = 0,
+    ) -> None: ...
+    def query(
+        self,
+        query: npt.NDArray[_D],
+        ef_search: int,
+        topk: int,
+        namespace: Optional[int] = None,
+    ) -> list[tuple[int, float]]: ...
+    def query_with_payload(
+        self, query: npt.NDArray[_D], ef_search: int, topk: int
+    ) -> list[tuple[int, float, bytes]]: ...
+    def query_filtered(
+        self,
+        query: npt.NDArray[_D],
+        ef_search: int,
+        topk: int,
+        filter: Callable[[int, bytes], bool],
+    ) -> list[tuple[int, float]]: ...
+    def query_with_filter(
+        self, query: npt.NDArray[_D], ef_search: int, topk: int, filter: Filter
+    ) -> list[tuple[int, float]]: ...
+    def remove(self, label: int) -> bool: ...
+    def save(self, path: Union[str, PathLike[str]]) -> None: ...
+    def __len__(self) -> int: ...
+    def __copy__(self) -> Any: ...
+    def __deepcopy__(self, memo: Any) -> Any: ...
+
+class _GraphIndex(_VectorIndex[_D]):
+    def __init__(self, max_nbrs: int, data_dim: int, capacity: int) -> None: ...
+    def reorder(self, reordering: Reordering) -> None: ...
+    def apply_permutation(
+        self, perm: Union[npt.NDArray[np.integer[Any]], Sequence[int]]
+    ) -> None: ...
+    def merge(
+        self, other: Self, ef_construction: int, n_samples: Optional[int] = None
+    ) -> None: ...
+    def namespace_stats(self) -> dict[int, NamespaceStats]: ...
+    def graph_stats(self) -> GraphStats: ...
+    def out_nodes(self) -> list[list[int]]: ...
+    def reordering_metrics(self, w: int = 5) -> ReorderingMetrics: ...
+
+class _FlatIndex(_VectorIndex[_D]):
+    def __init__(self, data_dim: int, capacity: int) -> None: ...
+
+class IndexEuclideanF32(_GraphIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanF32: ...
+
+class IndexEuclideanU8(_GraphIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanU8: ...
+
+class IndexEuclideanI8(_GraphIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanI8: ...
+
+class IndexInnerProductF32(_GraphIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductF32: ...
+
+class IndexInnerProductU8(_GraphIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductU8: ...
+
+class IndexInnerProductI8(_GraphIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductI8: ...
+
+class IndexEuclideanF32U16(_GraphIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanF32U16: ...
+
+class IndexEuclideanU8U16(_GraphIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanU8U16: ...
+
+class IndexEuclideanI8U16(_GraphIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanI8U16: ...
+
+class IndexInnerProductF32U16(_GraphIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductF32U16: ...
+
+class IndexInnerProductU8U16(_GraphIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductU8U16: ...
+
+class IndexInnerProductI8U16(_GraphIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductI8U16: ...
+
+class IndexEuclideanF32U64(_GraphIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanF32U64: ...
+
+class IndexEuclideanU8U64(_GraphIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanU8U64: ...
+
+class IndexEuclideanI8U64(_GraphIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexEuclideanI8U64: ...
+
+class IndexInnerProductF32U64(_GraphIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductF32U64: ...
+
+class IndexInnerProductU8U64(_GraphIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductU8U64: ...
+
+class IndexInnerProductI8U64(_GraphIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> IndexInnerProductI8U64: ...
+
+class FlatIndexEuclideanF32(_FlatIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> FlatIndexEuclideanF32: ...
+
+class FlatIndexEuclideanU8(_FlatIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> FlatIndexEuclideanU8: ...
+
+class FlatIndexEuclideanI8(_FlatIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> FlatIndexEuclideanI8: ...
+
+class FlatIndexInnerProductF32(_FlatIndex[np.float32]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> FlatIndexInnerProductF32: ...
+
+class FlatIndexInnerProductU8(_FlatIndex[np.uint8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> FlatIndexInnerProductU8: ...
+
+class FlatIndexInnerProductI8(_FlatIndex[np.int8]):
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> FlatIndexInnerProductI8: ...
+
+# Node ids are uint32, or uint64 when capacity does not fit in uint32, unless
+# nbr_type is given. uint16 limits the index to 65535 vectors and is only used
+# when requested.
+def Index(
+    max_nbrs: int,
+    data_dim: int,
+    capacity: int = 0,
+    metric: _Metric = "l2",
+    dtype: Optional[_DType] = None,
+    nbr_type: Optional[Literal["uint16", "uint32", "uint64"]] = None,
+) -> _GraphIndex[Any]: ...
+def FlatIndex(
+    data_dim: int,
+    capacity: int = 0,
+    metric: _Metric = "l2",
+    dtype: Optional[_DType] = None,
+) -> _FlatIndex[Any]: ...
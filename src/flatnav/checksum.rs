@@ -0,0 +1,173 @@
+use super::error::{Error, Result};
+use super::storage::read_u32;
+use std::io::{Read, Write};
+
+// CRC-32 with the IEEE polynomial, as used by zlib and gzip.
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    return table;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = TABLE[((self.state ^ byte as u32) & 0xff) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        return !self.state;
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    return crc.finish();
+}
+
+// Writer that computes the CRC-32 of a file section by section. Each
+// checksum covers the bytes written since the previous one.
+pub(super) struct ChecksumWriter<'a, W: Write> {
+    inner: &'a mut W,
+    crc: Crc32,
+}
+
+impl<'a, W: Write> ChecksumWriter<'a, W> {
+    pub(super) fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            crc: Crc32::new(),
+        }
+    }
+
+    pub(super) fn write_checksum(&mut self) -> Result<()> {
+        let crc = std::mem::take(&mut self.crc).finish();
+        self.inner.write_all(&crc.to_le_bytes())?;
+        return Ok(());
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        return Ok(n);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+// Reader counterpart of ChecksumWriter.
+pub(super) struct ChecksumReader<'a, R: Read> {
+    inner: &'a mut R,
+    crc: Crc32,
+}
+
+impl<'a, R: Read> ChecksumReader<'a, R> {
+    pub(super) fn new(inner: &'a mut R) -> Self {
+        Self {
+            inner,
+            crc: Crc32::new(),
+        }
+    }
+
+    // Reads the stored checksum of the section that was just read and
+    // compares it to the computed one.
+    pub(super) fn verify_checksum(&mut self, section: &str) -> Result<()> {
+        let crc = std::mem::take(&mut self.crc).finish();
+        let stored = read_u32(self.inner)?;
+        if stored != crc {
+            return Err(Error::InvalidFormat(format!(
+                "checksum mismatch in {}",
+                section
+            )));
+        }
+        return Ok(());
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        return Ok(n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf43926);
+
+        let mut buf = Vec::new();
+        let mut writer = ChecksumWriter::new(&mut buf);
+        writer.write_all(b"1234").unwrap();
+        writer.write_checksum().unwrap();
+        writer.write_all(b"56789").unwrap();
+        writer.write_checksum().unwrap();
+        assert_eq!(buf.len(), 17);
+
+        let mut slice = buf.as_slice();
+        let mut reader = ChecksumReader::new(&mut slice);
+        let mut section = [0; 4];
+        reader.read_exact(&mut section).unwrap();
+        reader.verify_checksum("first").unwrap();
+        let mut section = [0; 5];
+        reader.read_exact(&mut section).unwrap();
+        reader.verify_checksum("second").unwrap();
+
+        buf[5] ^= 1;
+        let mut slice = buf.as_slice();
+        let mut reader = ChecksumReader::new(&mut slice);
+        let mut section = [0; 4];
+        reader.read_exact(&mut section).unwrap();
+        assert!(matches!(
+            reader.verify_checksum("first"),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+}
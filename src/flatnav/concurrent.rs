@@ -0,0 +1,637 @@
+use super::distance::Distance;
+use super::error::{Error, Result};
+use super::search::{self, Graph};
+use super::storage::{pad_to, LabelT};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{RwLock, RwLockWriteGuard};
+
+// Fixed capacity version of InMemStorage that can be shared between threads.
+// Uses the same node layout, but the buffer is allocated up front so that it
+// never moves, the node count is atomic, and each neighbor list is guarded by
+// its own lock. Labels and data are written once by the inserting thread
+// before the node is marked ready and are read only afterwards.
+// The accessors rely on their callers in this module to only pass nodes that
+// are ready, or for init_node a node that the caller reserved itself, so the
+// type is kept private.
+struct ConcurrentStorage<NbrT, DataT> {
+    n_nodes: AtomicUsize,
+    capacity: usize,
+    nbr_offset: usize,
+    max_nbrs: usize,
+    data_offset: usize,
+    data_dim: usize,
+    node_size: usize,
+    // Backed by u64 so that every node, which is padded to a multiple of 8
+    // bytes, starts at an aligned address.
+    data: Box<[UnsafeCell<u64>]>,
+    ready: Box<[AtomicBool]>,
+    locks: Box<[RwLock<()>]>,
+    _a: PhantomData<NbrT>,
+    _b: PhantomData<DataT>,
+}
+
+// Safety: all mutation of the shared buffer goes through either the per node
+// locks (neighbor lists) or happens before the node is published with a
+// release store to `ready` (labels and data).
+unsafe impl<NbrT: Send + Sync, DataT: Send + Sync> Sync for ConcurrentStorage<NbrT, DataT> {}
+unsafe impl<NbrT: Send, DataT: Send> Send for ConcurrentStorage<NbrT, DataT> {}
+
+struct NbrsGuard<'a, NbrT> {
+    _guard: RwLockWriteGuard<'a, ()>,
+    nbrs: &'a mut [NbrT],
+}
+
+impl<NbrT> std::ops::Deref for NbrsGuard<'_, NbrT> {
+    type Target = [NbrT];
+
+    fn deref(&self) -> &[NbrT] {
+        return self.nbrs;
+    }
+}
+
+impl<NbrT> std::ops::DerefMut for NbrsGuard<'_, NbrT> {
+    fn deref_mut(&mut self) -> &mut [NbrT] {
+        return self.nbrs;
+    }
+}
+
+struct SetOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for SetOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+impl<NbrT, DataT> ConcurrentStorage<NbrT, DataT>
+where
+    NbrT: Copy,
+    DataT: Copy,
+{
+    fn new(max_nbrs: usize, data_dim: usize, capacity: usize) -> Self {
+        let nbr_offset = pad_to(std::mem::size_of::<LabelT>(), std::mem::align_of::<NbrT>());
+        let data_offset = pad_to(
+            nbr_offset + max_nbrs * std::mem::size_of::<NbrT>(),
+            std::mem::align_of::<DataT>(),
+        );
+        let node_size = pad_to(
+            data_offset + data_dim * std::mem::size_of::<DataT>(),
+            std::mem::align_of::<LabelT>(),
+        );
+
+        let n_words = node_size * capacity / std::mem::size_of::<u64>();
+
+        Self {
+            n_nodes: AtomicUsize::new(0),
+            capacity,
+            nbr_offset,
+            max_nbrs,
+            data_offset,
+            data_dim,
+            node_size,
+            data: (0..n_words).map(|_| UnsafeCell::new(0)).collect(),
+            ready: (0..capacity).map(|_| AtomicBool::new(false)).collect(),
+            locks: (0..capacity).map(|_| RwLock::new(())).collect(),
+            _a: PhantomData,
+            _b: PhantomData,
+        }
+    }
+
+    fn ptr(&self, node: usize, offset: usize) -> *mut u8 {
+        debug_assert!(node < self.capacity);
+        return unsafe {
+            (UnsafeCell::raw_get(self.data.as_ptr()) as *mut u8).add(node * self.node_size + offset)
+        };
+    }
+
+    // Reserves the id for a new node, which is not visible to readers until
+    // init_node is called. Ids are only handed out below both the capacity and
+    // max_nodes, in a single atomic update so that concurrent inserts cannot
+    // race past either limit.
+    fn reserve(&self, max_nodes: usize) -> Result<usize> {
+        return self
+            .n_nodes
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.capacity && n < max_nodes).then_some(n + 1)
+            })
+            .map_err(|n| {
+                if n >= max_nodes {
+                    Error::CapacityOverflow {
+                        len: n,
+                        max: max_nodes,
+                    }
+                } else {
+                    Error::CapacityExceeded(self.capacity)
+                }
+            });
+    }
+
+    fn init_node(&self, node: usize, label: LabelT, nbrs: &[NbrT], data: &[DataT]) -> Result<()> {
+        if node >= self.len() || self.is_ready(node) {
+            return Err(Error::InvalidGraph(format!(
+                "node {} is not reserved for initialization",
+                node
+            )));
+        }
+        if nbrs.len() != self.max_nbrs {
+            return Err(Error::NeighborCountMismatch {
+                expected: self.max_nbrs,
+                got: nbrs.len(),
+            });
+        }
+        if data.len() != self.data_dim {
+            return Err(Error::DimensionMismatch {
+                expected: self.data_dim,
+                got: data.len(),
+            });
+        }
+
+        // Safety: the node was reserved by the caller and is not yet ready, so
+        // no other thread reads or writes it.
+        unsafe {
+            std::ptr::write(self.ptr(node, 0) as *mut LabelT, label);
+            std::ptr::copy_nonoverlapping(
+                nbrs.as_ptr(),
+                self.ptr(node, self.nbr_offset) as *mut NbrT,
+                self.max_nbrs,
+            );
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.ptr(node, self.data_offset) as *mut DataT,
+                self.data_dim,
+            );
+        }
+
+        self.ready[node].store(true, Ordering::Release);
+        return Ok(());
+    }
+
+    fn is_ready(&self, node: usize) -> bool {
+        return self.ready[node].load(Ordering::Acquire);
+    }
+
+    fn label(&self, node: usize) -> LabelT {
+        debug_assert!(self.is_ready(node));
+        return unsafe { std::ptr::read(self.ptr(node, 0) as *const LabelT) };
+    }
+
+    fn data(&self, node: usize) -> &[DataT] {
+        debug_assert!(self.is_ready(node));
+        return unsafe {
+            std::slice::from_raw_parts(
+                self.ptr(node, self.data_offset) as *const DataT,
+                self.data_dim,
+            )
+        };
+    }
+
+    fn nbrs_mut(&self, node: usize) -> NbrsGuard<'_, NbrT> {
+        debug_assert!(self.is_ready(node));
+        let guard = self.locks[node].write().unwrap_or_else(|e| e.into_inner());
+        let nbrs = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.ptr(node, self.nbr_offset) as *mut NbrT,
+                self.max_nbrs,
+            )
+        };
+        return NbrsGuard {
+            _guard: guard,
+            nbrs,
+        };
+    }
+
+    // Number of reserved nodes, some of which may still be initializing.
+    fn len(&self) -> usize {
+        return self.n_nodes.load(Ordering::Acquire);
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    fn max_nbrs(&self) -> usize {
+        return self.max_nbrs;
+    }
+
+    fn data_dim(&self) -> usize {
+        return self.data_dim;
+    }
+}
+
+impl<NbrT, DataT> Graph<NbrT, DataT> for ConcurrentStorage<NbrT, DataT>
+where
+    NbrT: Copy,
+    DataT: Copy,
+{
+    fn max_nbrs(&self) -> usize {
+        return self.max_nbrs;
+    }
+
+    fn data(&self, node: usize) -> &[DataT] {
+        return ConcurrentStorage::data(self, node);
+    }
+
+    // The read lock is held while f runs so that it never sees a partially
+    // updated list.
+    fn with_nbrs<R, F: FnOnce(&[NbrT]) -> R>(&self, node: usize, f: F) -> R {
+        debug_assert!(self.is_ready(node));
+        let _guard = self.locks[node].read().unwrap_or_else(|e| e.into_inner());
+        let nbrs = unsafe {
+            std::slice::from_raw_parts(
+                self.ptr(node, self.nbr_offset) as *const NbrT,
+                self.max_nbrs,
+            )
+        };
+        return f(nbrs);
+    }
+}
+
+// Graph index whose insert and query both take &self, so a single instance can
+// be shared between ingestion and serving threads without an external lock.
+// Capacity is fixed at construction.
+pub struct ConcurrentIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    graph: ConcurrentStorage<NbrT, DataT>,
+    distance_fn: DistanceFn,
+    // Set if the insert of node 0 failed or panicked, so that later inserts
+    // waiting for it return an error instead of waiting forever.
+    first_failed: AtomicBool,
+}
+
+impl<NbrT, DataT, DistanceFn> ConcurrentIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    pub fn new(max_nbrs: usize, data_dim: usize, capacity: usize, distance_fn: DistanceFn) -> Self {
+        let graph = ConcurrentStorage::<NbrT, DataT>::new(max_nbrs, data_dim, capacity);
+        Self {
+            graph,
+            distance_fn,
+            first_failed: AtomicBool::new(false),
+        }
+    }
+
+    fn dist(&self, a: &[DataT], b: &[DataT]) -> f32 {
+        return self.distance_fn.call(a, b);
+    }
+
+    fn max_nodes() -> usize {
+        // NbrT::max_value() is reserved as the empty neighbor sentinel.
+        return NbrT::max_value().as_();
+    }
+
+    fn check_dim(&self, data: &[DataT]) -> Result<()> {
+        if data.len() != self.graph.data_dim() {
+            return Err(Error::DimensionMismatch {
+                expected: self.graph.data_dim(),
+                got: data.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    fn connect_neighbors(&self, neighbors: &[NbrT], new_node: NbrT, new_data: &[DataT]) {
+        for &nbr in neighbors {
+            if nbr == NbrT::max_value() {
+                continue;
+            }
+
+            // Only one neighbor list is locked at a time, so inserts cannot
+            // deadlock with each other.
+            let mut nbr_nbrs = self.graph.nbrs_mut(nbr.as_());
+            search::add_neighbor(
+                &self.graph,
+                &self.distance_fn,
+                nbr.as_(),
+                &mut nbr_nbrs,
+                new_node,
+                new_data,
+            );
+        }
+    }
+
+    fn search_initialization(&self, query: &[DataT], num_initializations: usize) -> Option<usize> {
+        let len = self.graph.len();
+        let step_size = (len / num_initializations).max(1);
+
+        let mut min_dist = f32::MAX;
+        let mut entry_node = None;
+
+        for node in (0..len).step_by(step_size) {
+            if !self.graph.is_ready(node) {
+                continue;
+            }
+            let dist = self.dist(query, self.graph.data(node));
+            if dist < min_dist {
+                min_dist = dist;
+                entry_node = Some(node);
+            }
+        }
+
+        return entry_node;
+    }
+
+    pub fn insert(&self, label: LabelT, data: &[DataT], ef_construction: usize) -> Result<()> {
+        self.check_dim(data)?;
+        let new_node = self.graph.reserve(Self::max_nodes())?;
+        // Cannot fail since reserve only returns ids below max_nodes, so the
+        // reserved node is always initialized below.
+        let new_id = NbrT::from_usize(new_node).unwrap();
+
+        if new_node == 0 {
+            // Marks the first node as failed if init_node returns an error or
+            // panics, and is forgotten once it succeeded.
+            let failed = SetOnDrop(&self.first_failed);
+            let neighbors = vec![NbrT::max_value(); self.graph.max_nbrs()];
+            self.graph.init_node(new_node, label, &neighbors, data)?;
+            std::mem::forget(failed);
+            return Ok(());
+        }
+
+        // Every later node links into the graph through the first one, so wait
+        // for it if it is still being written.
+        while !self.graph.is_ready(0) {
+            if self.first_failed.load(Ordering::Acquire) {
+                return Err(Error::InvalidGraph(
+                    "the insert of the first node failed".to_string(),
+                ));
+            }
+            std::thread::yield_now();
+        }
+
+        let entry = self.search_initialization(data, 100).unwrap_or(0);
+        let entry = NbrT::from_usize(entry).unwrap();
+
+        let candidates =
+            search::beam_search(&self.graph, &self.distance_fn, data, entry, ef_construction);
+        let neighbors = search::select_neighbors(&self.graph, &self.distance_fn, candidates);
+
+        self.graph.init_node(new_node, label, &neighbors, data)?;
+
+        self.connect_neighbors(&neighbors, new_id, data);
+
+        return Ok(());
+    }
+
+    pub fn query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+
+        let entry = self
+            .search_initialization(query, 100)
+            .ok_or(Error::EmptyIndex)?;
+
+        let mut results = search::beam_search(
+            &self.graph,
+            &self.distance_fn,
+            query,
+            NbrT::from_usize(entry).unwrap(),
+            ef_search,
+        );
+
+        let mut output = Vec::with_capacity(topk);
+        while output.len() < topk && !results.is_empty() {
+            let best = results.pop().unwrap();
+            output.push((self.graph.label(best.node.as_()), best.dist));
+        }
+
+        return Ok(output);
+    }
+
+    // Neighbors of the node, or None if it does not exist or is still being
+    // inserted.
+    pub fn nbrs(&self, node: usize) -> Option<Vec<NbrT>> {
+        if node >= self.graph.len() || !self.graph.is_ready(node) {
+            return None;
+        }
+        return Some(self.graph.with_nbrs(node, |nbrs| nbrs.to_vec()));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.graph.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.graph.is_empty();
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.graph.capacity();
+    }
+
+    pub fn data_dim(&self) -> usize {
+        return self.graph.data_dim();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_concurrent_storage() {
+        let storage = ConcurrentStorage::<u16, f32>::new(3, 2, 2);
+
+        let a = storage.reserve(usize::MAX).unwrap();
+        assert_eq!(
+            storage.reserve(1),
+            Err(Error::CapacityOverflow { len: 1, max: 1 })
+        );
+        let b = storage.reserve(usize::MAX).unwrap();
+        assert_eq!((a, b), (0, 1));
+        assert_eq!(storage.reserve(usize::MAX), Err(Error::CapacityExceeded(2)));
+
+        assert_eq!(
+            storage.init_node(b, 20, &[0, u16::MAX], &[1.0, 2.0]),
+            Err(Error::NeighborCountMismatch {
+                expected: 3,
+                got: 2
+            })
+        );
+        assert_eq!(
+            storage.init_node(b, 20, &[0, u16::MAX, u16::MAX], &[1.0]),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                got: 1
+            })
+        );
+        storage
+            .init_node(b, 20, &[0, u16::MAX, u16::MAX], &[1.0, 2.0])
+            .unwrap();
+        assert!(storage
+            .init_node(b, 20, &[0, u16::MAX, u16::MAX], &[1.0, 2.0])
+            .is_err());
+        assert!(!storage.is_ready(a));
+        assert!(storage.is_ready(b));
+
+        storage.init_node(a, 10, &[1, 1, 1], &[3.0, 4.0]).unwrap();
+        storage.nbrs_mut(a)[2] = u16::MAX;
+
+        assert_eq!(
+            storage.with_nbrs(a, |nbrs| nbrs.to_vec()),
+            vec![1, 1, u16::MAX]
+        );
+        assert_eq!(storage.label(a), 10);
+        assert_eq!(storage.data(a), &[3.0, 4.0]);
+        assert_eq!(storage.label(b), 20);
+        assert_eq!(storage.data(b), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_concurrent_index_overflow() {
+        let index =
+            ConcurrentIndex::<u8, f32, EuclideanDistance>::new(4, 1, 300, EuclideanDistance);
+        for i in 0..255 {
+            index.insert(i, &[i as f32], 16).unwrap();
+        }
+        assert_eq!(
+            index.insert(255, &[255.0], 16),
+            Err(Error::CapacityOverflow { len: 255, max: 255 })
+        );
+        assert_eq!(index.len(), 255);
+        assert!((0..255).all(|node| index.nbrs(node).is_some()));
+    }
+
+    #[test]
+    fn test_concurrent_index_first_insert_failed() {
+        let index = ConcurrentIndex::<u32, f32, EuclideanDistance>::new(4, 1, 8, EuclideanDistance);
+
+        // Node 0 is reserved, but its insert unwinds before the node is written.
+        assert_eq!(index.graph.reserve(usize::MAX), Ok(0));
+        drop(SetOnDrop(&index.first_failed));
+
+        assert!(matches!(
+            index.insert(1, &[1.0], 16),
+            Err(Error::InvalidGraph(_))
+        ));
+        assert_eq!(index.query(&[1.0], 16, 1), Err(Error::EmptyIndex));
+    }
+
+    #[test]
+    fn test_concurrent_index_stress() {
+        const MAX_NBRS: usize = 8;
+        const DATA_DIM: usize = 8;
+        const N_WRITERS: usize = 4;
+        const N_READERS: usize = 4;
+        const PER_WRITER: usize = 500;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let data_dist = rand::distr::Uniform::new(-1.0, 1.0).unwrap();
+        let dataset: Arc<Vec<Vec<f32>>> = Arc::new(
+            (0..N_WRITERS * PER_WRITER)
+                .map(|_| (0..DATA_DIM).map(|_| rng.sample(data_dist)).collect())
+                .collect(),
+        );
+
+        let index = Arc::new(ConcurrentIndex::<u32, f32, EuclideanDistance>::new(
+            MAX_NBRS,
+            DATA_DIM,
+            dataset.len(),
+            EuclideanDistance,
+        ));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let writers: Vec<_> = (0..N_WRITERS)
+            .map(|w| {
+                let (index, dataset) = (index.clone(), dataset.clone());
+                std::thread::spawn(move || {
+                    for i in (w * PER_WRITER)..((w + 1) * PER_WRITER) {
+                        index.insert(i as u64, &dataset[i], 32).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let readers: Vec<_> = (0..N_READERS)
+            .map(|r| {
+                let (index, dataset, done) = (index.clone(), dataset.clone(), done.clone());
+                std::thread::spawn(move || {
+                    let mut rng = rand::rngs::StdRng::seed_from_u64(r as u64);
+                    let mut n_checked = 0;
+                    while !done.load(Ordering::Acquire) || n_checked == 0 {
+                        let len = index.len();
+                        if len == 0 {
+                            continue;
+                        }
+
+                        let node = rng.random_range(0..len);
+                        if let Some(nbrs) = index.nbrs(node) {
+                            check_nbrs(&nbrs, node, &index);
+                            n_checked += 1;
+                        }
+
+                        let query = &dataset[rng.random_range(0..dataset.len())];
+                        if let Ok(results) = index.query(query, 16, 4) {
+                            assert!(!results.is_empty());
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        writers.into_iter().for_each(|x| x.join().unwrap());
+        done.store(true, Ordering::Release);
+        readers.into_iter().for_each(|x| x.join().unwrap());
+
+        assert_eq!(index.len(), dataset.len());
+        for node in 0..index.len() {
+            check_nbrs(&index.nbrs(node).unwrap(), node, &index);
+        }
+        assert_eq!(index.nbrs(index.len()), None);
+
+        let hits = dataset
+            .iter()
+            .enumerate()
+            .filter(|(i, vec)| index.query(vec, 32, 1).unwrap()[0].0 == *i as u64)
+            .count();
+        assert!(hits * 10 >= dataset.len() * 9, "only {} hits", hits);
+    }
+
+    // A consistent neighbor list has its filled slots first, no duplicates and
+    // only refers to other nodes that have been fully written.
+    fn check_nbrs(nbrs: &[u32], node: usize, index: &ConcurrentIndex<u32, f32, EuclideanDistance>) {
+        let filled: Vec<u32> = nbrs
+            .iter()
+            .copied()
+            .take_while(|&x| x != u32::MAX)
+            .collect();
+        assert!(nbrs[filled.len()..].iter().all(|&x| x == u32::MAX));
+
+        let unique: HashSet<u32> = filled.iter().copied().collect();
+        assert_eq!(unique.len(), filled.len(), "duplicate neighbors {:?}", nbrs);
+
+        for &nbr in &filled {
+            assert_ne!(nbr as usize, node);
+            assert!(index.graph.is_ready(nbr as usize));
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use super::distance::{Distance, SeparableDistance};
+use super::error::{Error, Result};
+use super::index::Index;
+use super::pq::ProductQuantizer;
+use super::priority_queue::{Closest, ClosestQueue, Furthest, FurthestQueue};
+use super::storage::{read_bytes, read_u32, read_u64, InMemStorage, LabelT};
+use std::borrow::Cow;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MAGIC: &[u8; 8] = b"FNDISK\0\0";
+const VERSION: u32 = 1;
+// The header is padded to one sector and node reads are widened to whole
+// sectors so that every read maps to complete pages.
+const SECTOR_SIZE: u64 = 4096;
+// Upper bound on the number of vectors the product quantizer is trained on.
+const MAX_TRAIN: usize = 20000;
+
+#[derive(Clone)]
+struct Record<NbrT, DataT> {
+    label: LabelT,
+    nbrs: Vec<NbrT>,
+    data: Vec<DataT>,
+}
+
+// Read-only graph index for indexes larger than memory. The node records are
+// kept in a file with the same [Label][Neighbors][Data] layout as
+// InMemStorage and read on demand, while only product quantized codes of the
+// vectors stay in memory. The beam search is guided by the approximate
+// distances from the codes, and the nodes it expands are reranked with the
+// exact distances of the vectors read along with their neighbors. Records of
+// the nodes around the entry points are cached when the index is opened.
+pub struct DiskIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + num_traits::AsPrimitive<f32> + Copy,
+    DistanceFn: Distance<DataT> + SeparableDistance,
+{
+    file: File,
+    file_len: u64,
+    max_nbrs: usize,
+    data_dim: usize,
+    n_nodes: usize,
+    node_size: usize,
+    pq: ProductQuantizer,
+    codes: Vec<u8>,
+    removed: Vec<bool>,
+    n_removed: usize,
+    // Nodes sampled evenly for choosing the entry point of a search.
+    entries: Vec<usize>,
+    cache: HashMap<usize, Record<NbrT, DataT>>,
+    distance_fn: DistanceFn,
+    n_reads: AtomicUsize,
+}
+
+impl<NbrT, DataT, DistanceFn> DiskIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + num_traits::AsPrimitive<f32> + Copy,
+    DistanceFn: Distance<DataT> + SeparableDistance,
+{
+    // Writes the graph of an in memory index to a disk index file, with its
+    // vectors quantized to n_subspaces bytes each. Payloads and namespaces
+    // are not written.
+    //
+    // File layout:
+    // [Magic][Version][NbrT size][DataT size][max_nbrs][data_dim][n_nodes]
+    // [node_size][Padding to SECTOR_SIZE][Node records]
+    // [Product quantizer][Codes][Removed u8 for each node]
+    pub fn build<P: AsRef<Path>>(
+        index: &Index<NbrT, DataT, DistanceFn>,
+        path: P,
+        n_subspaces: usize,
+    ) -> Result<()> {
+        let storage = index.storage();
+        let n_nodes = storage.len();
+
+        let step = (n_nodes / MAX_TRAIN).max(1);
+        let sample: Vec<Vec<f32>> = (0..n_nodes)
+            .step_by(step)
+            .filter(|&node| !storage.is_removed(node))
+            .map(|node| to_f32(storage.data(node)))
+            .collect();
+        let pq = ProductQuantizer::train(&sample, n_subspaces)?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        let mut header = Vec::with_capacity(SECTOR_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&(std::mem::size_of::<NbrT>() as u32).to_le_bytes());
+        header.extend_from_slice(&(std::mem::size_of::<DataT>() as u32).to_le_bytes());
+        header.extend_from_slice(&(storage.max_nbrs() as u64).to_le_bytes());
+        header.extend_from_slice(&(storage.data_dim() as u64).to_le_bytes());
+        header.extend_from_slice(&(n_nodes as u64).to_le_bytes());
+        let node_size =
+            InMemStorage::<NbrT, DataT>::node_size(storage.max_nbrs(), storage.data_dim());
+        header.extend_from_slice(&(node_size as u64).to_le_bytes());
+        header.resize(SECTOR_SIZE as usize, 0);
+        writer.write_all(&header)?;
+
+        for node in 0..n_nodes {
+            writer.write_all(storage.node_bytes(node))?;
+        }
+
+        pq.save(&mut writer)?;
+        let mut codes = Vec::with_capacity(n_nodes * pq.n_subspaces());
+        for node in 0..n_nodes {
+            pq.encode(&to_f32(storage.data(node)), &mut codes);
+        }
+        writer.write_all(&codes)?;
+        let removed: Vec<u8> = (0..n_nodes)
+            .map(|node| storage.is_removed(node) as u8)
+            .collect();
+        writer.write_all(&removed)?;
+        writer.flush()?;
+
+        return Ok(());
+    }
+
+    // Opens a disk index and caches the records of up to cache_size nodes,
+    // found by a breadth first search from the entry points.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        distance_fn: DistanceFn,
+        cache_size: usize,
+    ) -> Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(&file);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidFormat("not a flatnav disk index".to_string()));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(Error::InvalidFormat(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let nbr_size = read_u32(&mut reader)? as usize;
+        let data_size = read_u32(&mut reader)? as usize;
+        if nbr_size != std::mem::size_of::<NbrT>() || data_size != std::mem::size_of::<DataT>() {
+            return Err(Error::InvalidFormat(format!(
+                "index was saved with {} byte neighbor ids and {} byte data, expected {} and {}",
+                nbr_size,
+                data_size,
+                std::mem::size_of::<NbrT>(),
+                std::mem::size_of::<DataT>()
+            )));
+        }
+
+        let max_nbrs = read_u64(&mut reader)? as usize;
+        let data_dim = read_u64(&mut reader)? as usize;
+        let n_nodes = read_u64(&mut reader)? as usize;
+        let node_size = read_u64(&mut reader)? as usize;
+        if node_size != InMemStorage::<NbrT, DataT>::checked_node_size(max_nbrs, data_dim)? {
+            return Err(Error::InvalidFormat(format!(
+                "unexpected node size {}",
+                node_size
+            )));
+        }
+
+        // The records have to fit in the file, which bounds n_nodes before
+        // anything is allocated for it.
+        let records_end = n_nodes
+            .checked_mul(node_size)
+            .and_then(|len| SECTOR_SIZE.checked_add(len as u64))
+            .filter(|&end| end <= file_len)
+            .ok_or_else(|| {
+                Error::InvalidFormat(format!("node count {} exceeds the file", n_nodes))
+            })?;
+        reader.seek(SeekFrom::Start(records_end))?;
+
+        let pq = ProductQuantizer::load(&mut reader)?;
+        if pq.data_dim() != data_dim {
+            return Err(Error::InvalidFormat(format!(
+                "quantizer dim {} does not match data dim {}",
+                pq.data_dim(),
+                data_dim
+            )));
+        }
+        let codes = read_bytes(&mut reader, n_nodes * pq.n_subspaces())?;
+        if !codes.chunks(pq.n_subspaces()).all(|code| pq.is_valid(code)) {
+            return Err(Error::InvalidFormat(
+                "code refers to a missing centroid".to_string(),
+            ));
+        }
+        let removed: Vec<bool> = read_bytes(&mut reader, n_nodes)?
+            .into_iter()
+            .map(|x| x != 0)
+            .collect();
+        drop(reader);
+
+        let mut index = Self {
+            file,
+            file_len,
+            max_nbrs,
+            data_dim,
+            n_nodes,
+            node_size,
+            pq,
+            codes,
+            n_removed: removed.iter().filter(|&&x| x).count(),
+            removed,
+            entries: (0..n_nodes).step_by((n_nodes / 100).max(1)).collect(),
+            cache: HashMap::new(),
+            distance_fn,
+            n_reads: AtomicUsize::new(0),
+        };
+        index.fill_cache(cache_size)?;
+        index.n_reads.store(0, Ordering::Relaxed);
+
+        return Ok(index);
+    }
+
+    fn fill_cache(&mut self, cache_size: usize) -> Result<()> {
+        let mut cache = HashMap::new();
+        let mut seen: HashSet<usize> = self.entries.iter().copied().collect();
+        let mut queue: VecDeque<usize> = self.entries.iter().copied().collect();
+        while let Some(node) = queue.pop_front() {
+            if cache.len() >= cache_size {
+                break;
+            }
+            let record = self.read_record(node)?;
+            for &nbr in &record.nbrs {
+                if nbr != NbrT::max_value() && seen.insert(nbr.as_()) {
+                    queue.push_back(nbr.as_());
+                }
+            }
+            cache.insert(node, record);
+        }
+        self.cache = cache;
+        return Ok(());
+    }
+
+    fn read_record(&self, node: usize) -> Result<Record<NbrT, DataT>> {
+        let offset = SECTOR_SIZE + (node * self.node_size) as u64;
+        let start = offset / SECTOR_SIZE * SECTOR_SIZE;
+        let end = (offset + self.node_size as u64).div_ceil(SECTOR_SIZE) * SECTOR_SIZE;
+        let mut buf = vec![0; (end.min(self.file_len) - start) as usize];
+        read_exact_at(&self.file, &mut buf, start)?;
+        self.n_reads.fetch_add(1, Ordering::Relaxed);
+
+        let bytes = &buf[(offset - start) as usize..][..self.node_size];
+        let nbr_offset = InMemStorage::<NbrT, DataT>::nbr_offset();
+        let data_offset = InMemStorage::<NbrT, DataT>::data_offset(self.max_nbrs);
+        // The buffer has no particular alignment, so the values are copied out
+        // with unaligned reads. Bounds follow from node_size.
+        let record = unsafe {
+            let ptr = bytes.as_ptr();
+            Record {
+                label: std::ptr::read_unaligned(ptr as *const LabelT),
+                nbrs: (0..self.max_nbrs)
+                    .map(|i| std::ptr::read_unaligned((ptr.add(nbr_offset) as *const NbrT).add(i)))
+                    .collect(),
+                data: (0..self.data_dim)
+                    .map(|i| {
+                        std::ptr::read_unaligned((ptr.add(data_offset) as *const DataT).add(i))
+                    })
+                    .collect(),
+            }
+        };
+        if let Some(nbr) = record
+            .nbrs
+            .iter()
+            .find(|&&nbr| nbr != NbrT::max_value() && nbr.as_() >= self.n_nodes)
+        {
+            return Err(Error::InvalidFormat(format!(
+                "node {} has neighbor {} out of range",
+                node,
+                nbr.as_()
+            )));
+        }
+        return Ok(record);
+    }
+
+    fn record(&self, node: usize) -> Result<Cow<'_, Record<NbrT, DataT>>> {
+        return match self.cache.get(&node) {
+            Some(record) => Ok(Cow::Borrowed(record)),
+            None => Ok(Cow::Owned(self.read_record(node)?)),
+        };
+    }
+
+    fn code(&self, node: usize) -> &[u8] {
+        let n_subspaces = self.pq.n_subspaces();
+        return &self.codes[node * n_subspaces..(node + 1) * n_subspaces];
+    }
+
+    pub fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        if query.len() != self.data_dim {
+            return Err(Error::DimensionMismatch {
+                expected: self.data_dim,
+                got: query.len(),
+            });
+        }
+        if self.n_nodes == 0 {
+            return Err(Error::EmptyIndex);
+        }
+
+        let table = self.pq.distance_table(&to_f32(query), &self.distance_fn);
+        let approx_dist = |node: usize| -> f32 {
+            return self
+                .pq
+                .table_distance(&table, self.code(node), &self.distance_fn);
+        };
+
+        let mut entry = self.entries[0];
+        let mut entry_dist = f32::MAX;
+        for &node in &self.entries {
+            let dist = approx_dist(node);
+            if dist < entry_dist {
+                entry = node;
+                entry_dist = dist;
+            }
+        }
+
+        let mut visited: HashSet<usize> = HashSet::new();
+        let mut candidates: ClosestQueue<usize> = BinaryHeap::new();
+        let mut worklist: FurthestQueue<usize> = BinaryHeap::with_capacity(ef_search + 1);
+        visited.insert(entry);
+        candidates.push(Closest {
+            node: entry,
+            dist: entry_dist,
+        });
+        worklist.push(Furthest {
+            node: entry,
+            dist: entry_dist,
+        });
+
+        let mut results = Vec::new();
+        while let Some(best_candidate) = candidates.pop() {
+            if worklist.len() >= ef_search && best_candidate.dist > worklist.peek().unwrap().dist {
+                break;
+            }
+
+            let record = self.record(best_candidate.node)?;
+            if !self.removed[best_candidate.node] {
+                let dist = Distance::<DataT>::call(&self.distance_fn, query, &record.data);
+                results.push((record.label, dist));
+            }
+
+            for &nbr in &record.nbrs {
+                if nbr == NbrT::max_value() || !visited.insert(nbr.as_()) {
+                    continue;
+                }
+                let node = nbr.as_();
+                let dist = approx_dist(node);
+                candidates.push(Closest { node, dist });
+                worklist.push(Furthest { node, dist });
+                if worklist.len() > ef_search {
+                    worklist.pop();
+                }
+            }
+        }
+
+        results.sort_by(|a, b| a.1.total_cmp(&b.1));
+        results.truncate(topk);
+        return Ok(results);
+    }
+
+    // Number of node records read from disk since the index was opened, not
+    // counting cache hits.
+    pub fn n_reads(&self) -> usize {
+        return self.n_reads.load(Ordering::Relaxed);
+    }
+
+    pub fn cache_len(&self) -> usize {
+        return self.cache.len();
+    }
+
+    // Number of vectors, not counting removed ones.
+    pub fn len(&self) -> usize {
+        self.n_nodes - self.n_removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn data_dim(&self) -> usize {
+        self.data_dim
+    }
+}
+
+fn to_f32<DataT: num_traits::AsPrimitive<f32>>(vec: &[DataT]) -> Vec<f32> {
+    return vec.iter().map(|x| x.as_()).collect();
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    return std::os::unix::fs::FileExt::read_exact_at(file, buf, offset);
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_disk_index() {
+        const DATA_DIM: usize = 16;
+        const N_VECS: usize = 1000;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(11);
+        let data_dist = rand::distr::Uniform::new(-1.0, 1.0).unwrap();
+        let dataset: Vec<Vec<f32>> = (0..N_VECS)
+            .map(|_| (0..DATA_DIM).map(|_| rng.sample(data_dist)).collect())
+            .collect();
+
+        let mut index =
+            Index::<u32, f32, EuclideanDistance>::new(16, DATA_DIM, 0, EuclideanDistance);
+        for (i, vec) in dataset.iter().enumerate() {
+            index.insert(i as u64, vec, 64);
+        }
+        index.remove(0);
+
+        let path = std::env::temp_dir().join(format!("flatnav-disk-{}.index", std::process::id()));
+        DiskIndex::build(&index, &path, 4).unwrap();
+        let disk =
+            DiskIndex::<u32, f32, EuclideanDistance>::open(&path, EuclideanDistance, 200).unwrap();
+        assert!(matches!(
+            DiskIndex::<u16, f32, EuclideanDistance>::open(&path, EuclideanDistance, 0),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        // A neighbor id past the last node is rejected when its record is read.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let nbr_offset = SECTOR_SIZE as usize + InMemStorage::<u32, f32>::nbr_offset();
+        bytes[nbr_offset..nbr_offset + 4].copy_from_slice(&(N_VECS as u32).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            DiskIndex::<u32, f32, EuclideanDistance>::open(&path, EuclideanDistance, 10),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        // So is a node count that does not fit in the file.
+        for n_nodes in [1u64 << 30, u64::MAX] {
+            bytes[36..44].copy_from_slice(&n_nodes.to_le_bytes());
+            std::fs::write(&path, &bytes).unwrap();
+            assert!(matches!(
+                DiskIndex::<u32, f32, EuclideanDistance>::open(&path, EuclideanDistance, 10),
+                Err(Error::InvalidFormat(_))
+            ));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(disk.len(), N_VECS - 1);
+        assert_eq!(disk.cache_len(), 200);
+        assert_eq!(disk.n_reads(), 0);
+
+        let mut found = 0;
+        for (i, vec) in dataset.iter().enumerate().skip(1) {
+            let results = disk.try_query(vec, 64, 1).unwrap();
+            if results[0] == (i as u64, 0.0) {
+                found += 1;
+            }
+        }
+        assert!(found >= N_VECS - 10, "found {} of {}", found, N_VECS - 1);
+        assert!(disk.n_reads() > 0);
+
+        assert_ne!(disk.try_query(&dataset[0], 64, 1).unwrap()[0].0, 0);
+        assert_eq!(
+            disk.try_query(&[0.0], 64, 1),
+            Err(Error::DimensionMismatch {
+                expected: DATA_DIM,
+                got: 1
+            })
+        );
+    }
+}
@@ -2,6 +2,7 @@ pub trait Distance<T> {
     fn call(&self, a: &[T], b: &[T]) -> f32;
 }
 
+#[derive(Clone, Copy, Debug, Default)]
 pub struct EuclideanDistance;
 
 impl Distance<f32> for EuclideanDistance {
@@ -13,3 +14,111 @@ impl Distance<f32> for EuclideanDistance {
             .sqrt()
     }
 }
+
+// Integer vectors, such as the .bvecs datasets, are compared exactly in i64,
+// which the squared differences of 8 bit values cannot overflow for any
+// dimension that fits in memory.
+impl Distance<u8> for EuclideanDistance {
+    fn call(&self, a: &[u8], b: &[u8]) -> f32 {
+        (a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| (x as i64 - y as i64).pow(2))
+            .sum::<i64>() as f32)
+            .sqrt()
+    }
+}
+
+impl Distance<i8> for EuclideanDistance {
+    fn call(&self, a: &[i8], b: &[i8]) -> f32 {
+        (a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| (x as i64 - y as i64).pow(2))
+            .sum::<i64>() as f32)
+            .sqrt()
+    }
+}
+
+// Negated inner product, so that smaller is closer as for the other distances.
+// Vectors normalized to unit length give cosine similarity.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InnerProductDistance;
+
+impl Distance<f32> for InnerProductDistance {
+    fn call(&self, a: &[f32], b: &[f32]) -> f32 {
+        -a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>()
+    }
+}
+
+impl Distance<u8> for InnerProductDistance {
+    fn call(&self, a: &[u8], b: &[u8]) -> f32 {
+        -(a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| x as i64 * y as i64)
+            .sum::<i64>() as f32)
+    }
+}
+
+impl Distance<i8> for InnerProductDistance {
+    fn call(&self, a: &[i8], b: &[i8]) -> f32 {
+        -(a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| x as i64 * y as i64)
+            .sum::<i64>() as f32)
+    }
+}
+
+// Distances computed as a function of a sum over the dimensions, so that the
+// sum can be split across subspaces. Product quantized codes are compared with
+// a query through a table of partial sums for each centroid of each subspace.
+pub trait SeparableDistance: Distance<f32> {
+    fn partial(&self, a: &[f32], b: &[f32]) -> f32;
+    fn finish(&self, sum: f32) -> f32;
+}
+
+impl SeparableDistance for EuclideanDistance {
+    fn partial(&self, a: &[f32], b: &[f32]) -> f32 {
+        return a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum();
+    }
+
+    fn finish(&self, sum: f32) -> f32 {
+        return sum.sqrt();
+    }
+}
+
+impl SeparableDistance for InnerProductDistance {
+    fn partial(&self, a: &[f32], b: &[f32]) -> f32 {
+        return -a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>();
+    }
+
+    fn finish(&self, sum: f32) -> f32 {
+        return sum;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distances() {
+        assert_eq!(EuclideanDistance.call(&[0.0f32, 3.0], &[4.0, 0.0]), 5.0);
+        assert_eq!(EuclideanDistance.call(&[0u8, 3], &[4, 0]), 5.0);
+        assert_eq!(EuclideanDistance.call(&[0u8, 255], &[255, 0]), 360.62445);
+        assert_eq!(EuclideanDistance.call(&[-128i8, 3], &[127, 0]), 255.01764);
+
+        assert_eq!(
+            InnerProductDistance.call(&[1.0f32, 2.0], &[3.0, -1.0]),
+            -1.0
+        );
+        assert_eq!(InnerProductDistance.call(&[255u8, 2], &[255, 1]), -65027.0);
+        assert_eq!(
+            InnerProductDistance.call(&[-128i8, 2], &[-128, 1]),
+            -16386.0
+        );
+
+        // Sums past i32::MAX.
+        let (a, b) = (vec![255u8; 40000], vec![0u8; 40000]);
+        assert_eq!(EuclideanDistance.call(&a, &b), (2601000000.0f32).sqrt());
+        assert_eq!(InnerProductDistance.call(&a, &a), -2601000000.0);
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    DimensionMismatch { expected: usize, got: usize },
+    NeighborCountMismatch { expected: usize, got: usize },
+    CapacityOverflow { len: usize, max: usize },
+    CapacityExceeded(usize),
+    InvalidPermutation(String),
+    InvalidGraph(String),
+    EmptyIndex,
+    InvalidFormat(String),
+    InvalidAttributes(String),
+    InvalidArgument(String),
+    Io(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DimensionMismatch { expected, got } => {
+                write!(f, "invalid data dim: expected {}, got {}", expected, got)
+            }
+            Error::NeighborCountMismatch { expected, got } => {
+                write!(
+                    f,
+                    "neighbor count mismatch: expected {}, got {}",
+                    expected, got
+                )
+            }
+            Error::CapacityOverflow { len, max } => {
+                write!(
+                    f,
+                    "cannot add node {}: node id type supports at most {} nodes",
+                    len, max
+                )
+            }
+            Error::CapacityExceeded(capacity) => {
+                write!(f, "index is full: capacity is {} nodes", capacity)
+            }
+            Error::InvalidPermutation(msg) => write!(f, "invalid permutation: {}", msg),
+            Error::InvalidGraph(msg) => write!(f, "invalid graph: {}", msg),
+            Error::EmptyIndex => write!(f, "index is empty"),
+            Error::InvalidFormat(msg) => write!(f, "invalid format: {}", msg),
+            Error::InvalidAttributes(msg) => write!(f, "invalid attributes: {}", msg),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            Error::Io(msg) => write!(f, "io error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err.to_string())
+    }
+}
@@ -0,0 +1,304 @@
+use super::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Tag(String),
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Int(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Tag(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Tag(value)
+    }
+}
+
+const INT: u8 = 0;
+const TAG: u8 = 1;
+
+// Named integer and tag attributes of a vector, stored in its payload. A name
+// may be repeated to give a vector several values, e.g. multiple tags.
+//
+// Encoding of each attribute:
+// [Name len u16][Name][Kind u8][Int i64 | Tag len u16, Tag]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    entries: Vec<(String, Value)>,
+}
+
+impl Attributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<V: Into<Value>>(&mut self, name: &str, value: V) {
+        self.entries.push((name.to_string(), value.into()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        return self
+            .entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value));
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for (name, value) in &self.entries {
+            write_str(&mut bytes, name)?;
+            match value {
+                Value::Int(x) => {
+                    bytes.push(INT);
+                    bytes.extend_from_slice(&x.to_le_bytes());
+                }
+                Value::Tag(tag) => {
+                    bytes.push(TAG);
+                    write_str(&mut bytes, tag)?;
+                }
+            }
+        }
+        return Ok(bytes);
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut entries = Vec::new();
+        for entry in Entries(bytes) {
+            let (name, value) = entry?;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| invalid("name is not utf8"))?;
+            let value = match value {
+                ValueRef::Int(x) => Value::Int(x),
+                ValueRef::Tag(tag) => Value::Tag(
+                    String::from_utf8(tag.to_vec()).map_err(|_| invalid("tag is not utf8"))?,
+                ),
+            };
+            entries.push((name, value));
+        }
+        return Ok(Self { entries });
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    return Error::InvalidAttributes(msg.to_string());
+}
+
+fn write_str(bytes: &mut Vec<u8>, s: &str) -> Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| {
+        let prefix: String = s.chars().take(16).collect();
+        invalid(&format!("'{}...' is longer than 65535 bytes", prefix))
+    })?;
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(s.as_bytes());
+    return Ok(());
+}
+
+enum ValueRef<'a> {
+    Int(i64),
+    Tag(&'a [u8]),
+}
+
+// Iterates over encoded attributes without copying them.
+struct Entries<'a>(&'a [u8]);
+
+impl<'a> Entries<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            self.0 = &[];
+            return Err(invalid("truncated attributes"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        return Ok(head);
+    }
+
+    fn take_str(&mut self) -> Result<&'a [u8]> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        return self.take(len as usize);
+    }
+
+    fn entry(&mut self) -> Result<(&'a [u8], ValueRef<'a>)> {
+        let name = self.take_str()?;
+        let value = match self.take(1)?[0] {
+            INT => ValueRef::Int(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            TAG => ValueRef::Tag(self.take_str()?),
+            kind => {
+                self.0 = &[];
+                return Err(invalid(&format!("unknown attribute kind {}", kind)));
+            }
+        };
+        return Ok((name, value));
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<(&'a [u8], ValueRef<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        return Some(self.entry());
+    }
+}
+
+// Filter over the attributes of a vector. Eq, In and Range match if any value
+// of the named attribute matches, Range bounds are inclusive and only match
+// integer attributes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Eq(String, Value),
+    In(String, Vec<Value>),
+    Range { name: String, min: i64, max: i64 },
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn eq<V: Into<Value>>(name: &str, value: V) -> Self {
+        Filter::Eq(name.to_string(), value.into())
+    }
+
+    pub fn is_in<V: Into<Value>>(name: &str, values: Vec<V>) -> Self {
+        Filter::In(
+            name.to_string(),
+            values.into_iter().map(|x| x.into()).collect(),
+        )
+    }
+
+    pub fn range(name: &str, min: i64, max: i64) -> Self {
+        Filter::Range {
+            name: name.to_string(),
+            min,
+            max,
+        }
+    }
+
+    // Vectors whose payload is not valid attributes never match.
+    pub fn matches(&self, payload: &[u8]) -> bool {
+        return self.eval(payload).unwrap_or(false);
+    }
+
+    fn eval(&self, payload: &[u8]) -> Result<bool> {
+        let any = |name: &str, pred: &dyn Fn(&ValueRef) -> bool| -> Result<bool> {
+            for entry in Entries(payload) {
+                let (entry_name, value) = entry?;
+                if entry_name == name.as_bytes() && pred(&value) {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        };
+
+        return match self {
+            Filter::Eq(name, expected) => any(name, &|value| value_eq(value, expected)),
+            Filter::In(name, expected) => any(name, &|value| {
+                expected.iter().any(|expected| value_eq(value, expected))
+            }),
+            Filter::Range { name, min, max } => any(name, &|value| match value {
+                ValueRef::Int(x) => min <= x && x <= max,
+                ValueRef::Tag(_) => false,
+            }),
+            Filter::And(filters) => {
+                for filter in filters {
+                    if !filter.eval(payload)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Filter::Or(filters) => {
+                for filter in filters {
+                    if filter.eval(payload)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Filter::Not(filter) => Ok(!filter.eval(payload)?),
+        };
+    }
+}
+
+fn value_eq(value: &ValueRef, expected: &Value) -> bool {
+    return match (value, expected) {
+        (ValueRef::Int(x), Value::Int(y)) => x == y,
+        (ValueRef::Tag(x), Value::Tag(y)) => *x == y.as_bytes(),
+        _ => false,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(category: &str, year: i64, tags: &[&str]) -> Vec<u8> {
+        let mut attributes = Attributes::new();
+        attributes.push("category", category);
+        attributes.push("year", year);
+        for &tag in tags {
+            attributes.push("tag", tag);
+        }
+        return attributes.encode().unwrap();
+    }
+
+    #[test]
+    fn test_attributes_encoding() {
+        let mut attributes = Attributes::new();
+        attributes.push("category", "shoes");
+        attributes.push("year", -2024);
+        let bytes = attributes.encode().unwrap();
+        assert_eq!(Attributes::decode(&bytes).unwrap(), attributes);
+        assert!(matches!(
+            Attributes::decode(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidAttributes(_))
+        ));
+
+        let mut attributes = Attributes::new();
+        attributes.push(&"x".repeat(70000), 0);
+        assert!(attributes.encode().is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let shoes = attributes("shoes", 2020, &["sale", "red"]);
+        let hats = attributes("hats", 2023, &[]);
+
+        let filter = Filter::eq("category", "shoes");
+        assert!(filter.matches(&shoes));
+        assert!(!filter.matches(&hats));
+        assert!(!Filter::eq("category", 1).matches(&shoes));
+
+        assert!(Filter::eq("tag", "red").matches(&shoes));
+        assert!(Filter::is_in("tag", vec!["blue", "sale"]).matches(&shoes));
+        assert!(!Filter::is_in("tag", vec!["blue"]).matches(&shoes));
+
+        assert!(Filter::range("year", 2020, 2022).matches(&shoes));
+        assert!(!Filter::range("year", 2020, 2022).matches(&hats));
+        assert!(!Filter::range("category", 0, i64::MAX).matches(&shoes));
+
+        let filter = Filter::And(vec![
+            Filter::range("year", 2000, 2030),
+            Filter::Not(Box::new(Filter::eq("tag", "sale"))),
+        ]);
+        assert!(!filter.matches(&shoes));
+        assert!(filter.matches(&hats));
+
+        let filter = Filter::Or(vec![Filter::eq("tag", "sale"), Filter::eq("year", 2023)]);
+        assert!(filter.matches(&shoes));
+        assert!(filter.matches(&hats));
+
+        assert!(Filter::And(vec![]).matches(&[]));
+        assert!(!Filter::Not(Box::new(Filter::eq("year", 1))).matches(b"garbage"));
+    }
+}
@@ -0,0 +1,334 @@
+use super::distance::Distance;
+use super::error::{Error, Result};
+use super::filter::{Attributes, Filter};
+use super::priority_queue::{Furthest, FurthestQueue};
+use super::storage::{InMemStorage, LabelT};
+use std::collections::{BinaryHeap, HashMap};
+
+// Exact index which answers queries by scanning every vector. The vectors are
+// kept in an InMemStorage without neighbor lists, so the layout, payloads,
+// namespaces and file format are the same as for the graph index. The
+// ef_construction and ef_search arguments are accepted so that it can be
+// swapped with Index, but are ignored.
+#[derive(Clone)]
+pub struct FlatIndex<DataT, DistanceFn>
+where
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    storage: InMemStorage<u32, DataT>,
+    distance_fn: DistanceFn,
+    namespace_lens: HashMap<u32, usize>,
+}
+
+impl<DataT, DistanceFn> FlatIndex<DataT, DistanceFn>
+where
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    pub fn new(data_dim: usize, capacity: usize, distance_fn: DistanceFn) -> Self {
+        Self {
+            storage: InMemStorage::new(0, data_dim, capacity),
+            distance_fn,
+            namespace_lens: HashMap::new(),
+        }
+    }
+
+    fn check_dim(&self, data: &[DataT]) -> Result<()> {
+        if data.len() != self.storage.data_dim() {
+            return Err(Error::DimensionMismatch {
+                expected: self.storage.data_dim(),
+                got: data.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    // Returns the topk closest nodes accepted by the filter, closest first.
+    fn scan<F: Fn(usize) -> bool>(
+        &self,
+        query: &[DataT],
+        topk: usize,
+        filter: F,
+    ) -> Result<Vec<(usize, f32)>> {
+        self.check_dim(query)?;
+
+        if self.storage.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
+
+        let mut worklist: FurthestQueue<usize> = BinaryHeap::with_capacity(topk + 1);
+        for node in 0..self.storage.len() {
+            if self.storage.is_removed(node) || !filter(node) {
+                continue;
+            }
+            let dist = self.distance_fn.call(query, self.storage.data(node));
+            if worklist.len() < topk || dist < worklist.peek().unwrap().dist {
+                worklist.push(Furthest { node, dist });
+                if worklist.len() > topk {
+                    worklist.pop();
+                }
+            }
+        }
+
+        return Ok(worklist
+            .into_sorted_vec()
+            .into_iter()
+            .map(|x| (x.node, x.dist))
+            .collect());
+    }
+
+    fn labeled(&self, results: Vec<(usize, f32)>) -> Vec<(LabelT, f32)> {
+        return results
+            .into_iter()
+            .map(|(node, dist)| (self.storage.label(node), dist))
+            .collect();
+    }
+
+    pub fn try_insert(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_with_payload(label, data, &[], ef_construction);
+    }
+
+    pub fn try_insert_with_payload(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_in_namespace(0, label, data, payload, ef_construction);
+    }
+
+    pub fn try_insert_with_attributes(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        attributes: &Attributes,
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_with_payload(label, data, &attributes.encode()?, ef_construction);
+    }
+
+    pub fn try_insert_in_namespace(
+        &mut self,
+        namespace: u32,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        _ef_construction: usize,
+    ) -> Result<()> {
+        self.check_dim(data)?;
+        self.storage
+            .add_node_in_namespace(namespace, label, &[], data, payload)?;
+        *self.namespace_lens.entry(namespace).or_insert(0) += 1;
+        return Ok(());
+    }
+
+    pub fn insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) {
+        if let Err(e) = self.try_insert(label, data, ef_construction) {
+            panic!("{}", e);
+        }
+    }
+
+    pub fn try_query(
+        &self,
+        query: &[DataT],
+        _ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        let results = self.scan(query, topk, |_| true)?;
+        return Ok(self.labeled(results));
+    }
+
+    pub fn try_query_with_payload(
+        &self,
+        query: &[DataT],
+        _ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32, &[u8])>> {
+        let results = self.scan(query, topk, |_| true)?;
+        return Ok(results
+            .into_iter()
+            .map(|(node, dist)| (self.storage.label(node), dist, self.storage.payload(node)))
+            .collect());
+    }
+
+    pub fn try_query_filtered<F: Fn(LabelT, &[u8]) -> bool>(
+        &self,
+        query: &[DataT],
+        _ef_search: usize,
+        topk: usize,
+        filter: F,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        let results = self.scan(query, topk, |node| {
+            filter(self.storage.label(node), self.storage.payload(node))
+        })?;
+        return Ok(self.labeled(results));
+    }
+
+    pub fn try_query_with_filter(
+        &self,
+        query: &[DataT],
+        _ef_search: usize,
+        topk: usize,
+        filter: &Filter,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        let results = self.scan(query, topk, |node| {
+            filter.matches(self.storage.payload(node))
+        })?;
+        return Ok(self.labeled(results));
+    }
+
+    pub fn try_query_in_namespace(
+        &self,
+        namespace: u32,
+        query: &[DataT],
+        _ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        let results = self.scan(query, topk, |node| {
+            self.storage.namespace(node) == namespace
+        })?;
+        return Ok(self.labeled(results));
+    }
+
+    pub fn query(&self, query: &[DataT], ef_search: usize, topk: usize) -> Vec<(LabelT, f32)> {
+        match self.try_query(query, ef_search, topk) {
+            Ok(results) => results,
+            Err(Error::EmptyIndex) => Vec::new(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // Removes all vectors with the label and returns whether there were any.
+    pub fn remove(&mut self, label: LabelT) -> bool {
+        let mut found = false;
+        for node in 0..self.storage.len() {
+            if self.storage.label(node) == label && self.storage.remove(node) {
+                *self
+                    .namespace_lens
+                    .get_mut(&self.storage.namespace(node))
+                    .unwrap() -= 1;
+                found = true;
+            }
+        }
+        return found;
+    }
+
+    pub fn namespace_len(&self, namespace: u32) -> usize {
+        return self.namespace_lens.get(&namespace).copied().unwrap_or(0);
+    }
+
+    pub fn save<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        return self.storage.save(writer);
+    }
+
+    pub fn load<R: std::io::Read>(reader: &mut R, distance_fn: DistanceFn) -> Result<Self> {
+        let storage = InMemStorage::<u32, DataT>::load(reader)?;
+        if storage.max_nbrs() != 0 {
+            return Err(Error::InvalidFormat(
+                "file contains a graph index, not a flat index".to_string(),
+            ));
+        }
+        let mut namespace_lens = HashMap::new();
+        for node in 0..storage.len() {
+            if !storage.is_removed(node) {
+                *namespace_lens.entry(storage.namespace(node)).or_insert(0) += 1;
+            }
+        }
+        return Ok(Self {
+            storage,
+            distance_fn,
+            namespace_lens,
+        });
+    }
+
+    // Number of vectors, not counting removed ones.
+    pub fn len(&self) -> usize {
+        self.storage.len() - self.storage.n_removed()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn data_dim(&self) -> usize {
+        self.storage.data_dim()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+    use crate::flatnav::index::Index;
+
+    #[test]
+    fn test_flat_index() {
+        let mut index = FlatIndex::<f32, EuclideanDistance>::new(2, 100, EuclideanDistance);
+        assert_eq!(index.try_query(&[0.0, 0.0], 0, 1), Err(Error::EmptyIndex));
+        assert!(matches!(
+            index.try_insert(0, &[0.0], 0),
+            Err(Error::DimensionMismatch { .. })
+        ));
+
+        for i in 0..100 {
+            let mut attributes = Attributes::new();
+            attributes.push("parity", i as i64 % 2);
+            index
+                .try_insert_with_attributes(i, &[i as f32, 0.0], &attributes, 0)
+                .unwrap();
+        }
+        index
+            .try_insert_in_namespace(3, 100, &[50.5, 0.0], b"x", 0)
+            .unwrap();
+
+        let results = index.query(&[50.2, 0.0], 0, 3);
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels, vec![50, 100, 51]);
+        assert!(results[0].1 <= results[1].1 && results[1].1 <= results[2].1);
+
+        let results = index
+            .try_query_with_filter(&[50.2, 0.0], 0, 2, &Filter::eq("parity", 1))
+            .unwrap();
+        assert_eq!(
+            results.iter().map(|x| x.0).collect::<Vec<_>>(),
+            vec![51, 49]
+        );
+
+        let results = index.try_query_in_namespace(3, &[0.0, 0.0], 0, 5).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(index.namespace_len(0), 100);
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let loaded =
+            FlatIndex::<f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance)
+                .unwrap();
+        assert_eq!(loaded.len(), 101);
+        assert_eq!(loaded.namespace_len(3), 1);
+        let results = loaded.try_query_with_payload(&[50.2, 0.0], 0, 2).unwrap();
+        assert_eq!(results[1], (100, results[1].1, &b"x"[..]));
+
+        let mut graph = Index::<u32, f32, EuclideanDistance>::new(4, 2, 1, EuclideanDistance);
+        graph.insert(0, &[0.0, 0.0], 16);
+        let mut buf = Vec::new();
+        graph.save(&mut buf).unwrap();
+        assert!(matches!(
+            FlatIndex::<f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        assert!(matches!(
+            Index::<u32, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+}
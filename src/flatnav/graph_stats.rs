@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+// Structure of a built graph, see Index::graph_stats. Removed nodes are
+// counted since searches still pass through them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GraphStats {
+    pub n_nodes: usize,
+    pub n_edges: usize,
+    // out_degrees[d] is the number of nodes with d neighbors, from 0 up to
+    // max_nbrs.
+    pub out_degrees: Vec<usize>,
+    // in_degrees[d] is the number of nodes that are the neighbor of d nodes.
+    pub in_degrees: Vec<usize>,
+    // Strongly connected components.
+    pub n_components: usize,
+    // Nodes that no search can reach from the entry points.
+    pub n_unreachable: usize,
+    // Mean distance between the vectors of the two ends of an edge.
+    pub avg_edge_length: f64,
+}
+
+pub(super) fn compute<F: Fn(usize, usize) -> f32>(
+    out_nodes: &[Vec<usize>],
+    max_nbrs: usize,
+    entries: &[usize],
+    edge_length: F,
+) -> GraphStats {
+    let n_nodes = out_nodes.len();
+    let mut out_degrees = vec![0; max_nbrs + 1];
+    let mut in_degree = vec![0; n_nodes];
+    let mut total_length = 0.0;
+    for (node, nbrs) in out_nodes.iter().enumerate() {
+        out_degrees[nbrs.len()] += 1;
+        for &nbr in nbrs {
+            in_degree[nbr] += 1;
+            total_length += edge_length(node, nbr) as f64;
+        }
+    }
+    let n_edges: usize = in_degree.iter().sum();
+
+    let mut in_degrees = vec![0; in_degree.iter().max().map_or(0, |&d| d + 1)];
+    for &d in &in_degree {
+        in_degrees[d] += 1;
+    }
+
+    return GraphStats {
+        n_nodes,
+        n_edges,
+        out_degrees,
+        in_degrees,
+        n_components: count_components(out_nodes),
+        n_unreachable: n_nodes - count_reachable(out_nodes, entries),
+        avg_edge_length: if n_edges == 0 {
+            0.0
+        } else {
+            total_length / n_edges as f64
+        },
+    };
+}
+
+fn count_reachable(out_nodes: &[Vec<usize>], entries: &[usize]) -> usize {
+    let mut seen = vec![false; out_nodes.len()];
+    let mut queue = VecDeque::new();
+    for &entry in entries {
+        if !seen[entry] {
+            seen[entry] = true;
+            queue.push_back(entry);
+        }
+    }
+    let mut n_reached = queue.len();
+    while let Some(node) = queue.pop_front() {
+        for &nbr in &out_nodes[node] {
+            if !seen[nbr] {
+                seen[nbr] = true;
+                n_reached += 1;
+                queue.push_back(nbr);
+            }
+        }
+    }
+    return n_reached;
+}
+
+// Number of strongly connected components, with Tarjan's algorithm run with an
+// explicit stack so that long paths cannot overflow the call stack.
+fn count_components(out_nodes: &[Vec<usize>]) -> usize {
+    const UNVISITED: usize = usize::MAX;
+    let n_nodes = out_nodes.len();
+    let mut order = vec![UNVISITED; n_nodes];
+    let mut low = vec![0; n_nodes];
+    let mut on_stack = vec![false; n_nodes];
+    let mut stack = Vec::new();
+    // Nodes being visited with the position of the next edge to follow.
+    let mut visits: Vec<(usize, usize)> = Vec::new();
+    let mut next_order = 0;
+    let mut n_components = 0;
+
+    for root in 0..n_nodes {
+        if order[root] != UNVISITED {
+            continue;
+        }
+        order[root] = next_order;
+        low[root] = next_order;
+        next_order += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        visits.push((root, 0));
+
+        while let Some(visit) = visits.last_mut() {
+            let node = visit.0;
+            if let Some(&nbr) = out_nodes[node].get(visit.1) {
+                visit.1 += 1;
+                if order[nbr] == UNVISITED {
+                    order[nbr] = next_order;
+                    low[nbr] = next_order;
+                    next_order += 1;
+                    stack.push(nbr);
+                    on_stack[nbr] = true;
+                    visits.push((nbr, 0));
+                } else if on_stack[nbr] {
+                    low[node] = low[node].min(order[nbr]);
+                }
+                continue;
+            }
+
+            visits.pop();
+            if let Some(&(parent, _)) = visits.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+            if low[node] == order[node] {
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member] = false;
+                    if member == node {
+                        break;
+                    }
+                }
+                n_components += 1;
+            }
+        }
+    }
+    return n_components;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph_stats() {
+        // 0 <-> 1 -> 2 <-> 3, 4 -> 3, and 5 on its own.
+        let out_nodes = vec![vec![1], vec![0, 2], vec![3], vec![2], vec![3], vec![]];
+        let stats = compute(&out_nodes, 2, &[0], |a, b| a.abs_diff(b) as f32);
+        assert_eq!(stats.n_nodes, 6);
+        assert_eq!(stats.n_edges, 6);
+        assert_eq!(stats.out_degrees, vec![1, 4, 1]);
+        assert_eq!(stats.in_degrees, vec![2, 2, 2]);
+        assert_eq!(stats.n_components, 4);
+        assert_eq!(stats.n_unreachable, 2);
+        assert_eq!(stats.avg_edge_length, 1.0);
+
+        let stats = compute(&[], 2, &[], |_, _| 0.0);
+        assert_eq!(stats.n_components, 0);
+        assert_eq!(stats.in_degrees, Vec::<usize>::new());
+        assert_eq!(stats.avg_edge_length, 0.0);
+    }
+}
@@ -1,11 +1,48 @@
 use super::distance::Distance;
+use super::error::{Error, Result};
+use super::filter::{Attributes, Filter};
+use super::graph_stats::{self, GraphStats};
 use super::priority_queue::{Closest, ClosestQueue, Furthest, FurthestQueue};
-use super::reordering::Reordering;
+use super::reordering::{self, Reordering};
+use super::search;
 use super::storage::InMemStorage;
-use std::collections::{BinaryHeap, HashSet};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 
 type LabelT = u64;
 
+// Number of nodes sampled to estimate how many nodes a filter matches.
+const FILTER_SAMPLE_SIZE: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NamespaceStats {
+    pub len: usize,
+    // Outgoing edges of the nodes in the namespace, and how many of them stay
+    // within the namespace.
+    pub edges: usize,
+    pub intra_edges: usize,
+}
+
+// Structural problems found by Index::verify. Edges are (node, neighbor) pairs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub n_nodes: usize,
+    pub n_edges: usize,
+    // Neighbor ids that are neither the empty marker nor a node of the graph.
+    pub out_of_range: Vec<(usize, usize)>,
+    pub self_loops: Vec<usize>,
+    // Neighbors listed more than once by the same node, reported once each.
+    pub duplicate_edges: Vec<(usize, usize)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        return self.out_of_range.is_empty()
+            && self.self_loops.is_empty()
+            && self.duplicate_edges.is_empty();
+    }
+}
+
+#[derive(Clone)]
 pub struct Index<NbrT, DataT, DistanceFn>
 where
     NbrT: num_traits::PrimInt
@@ -18,6 +55,10 @@ where
 {
     graph: InMemStorage<NbrT, DataT>,
     distance_fn: DistanceFn,
+    namespace_lens: HashMap<u32, usize>,
+    // Nodes of each namespace, including removed ones, so that small
+    // namespaces can be scanned without going over the whole graph.
+    namespace_nodes: HashMap<u32, Vec<NbrT>>,
 }
 
 impl<NbrT, DataT, DistanceFn> Index<NbrT, DataT, DistanceFn>
@@ -32,18 +73,33 @@ where
 {
     pub fn new(max_nbrs: usize, data_dim: usize, capacity: usize, distance_fn: DistanceFn) -> Self {
         let graph = InMemStorage::<NbrT, DataT>::new(max_nbrs, data_dim, capacity);
-        Self { graph, distance_fn }
+        Self {
+            graph,
+            distance_fn,
+            namespace_lens: HashMap::new(),
+            namespace_nodes: HashMap::new(),
+        }
     }
 
     fn dist(&self, a: &[DataT], b: &[DataT]) -> f32 {
         return self.distance_fn.call(a, b);
     }
 
-    pub fn beam_search(
+    // entry must be a node of the graph, it is not checked in release builds.
+    fn beam_search(&self, query: &[DataT], entry: NbrT, ef_search: usize) -> ClosestQueue<NbrT> {
+        return search::beam_search(&self.graph, &self.distance_fn, query, entry, ef_search);
+    }
+
+    // Like beam_search, but only nodes accepted by the filter are kept in the
+    // results. Rejected nodes are still expanded so that the search can move
+    // through regions of the graph where nothing matches, and the search only
+    // stops early once ef_search matching nodes have been found.
+    pub(crate) fn filtered_beam_search<F: Fn(usize) -> bool>(
         &self,
         query: &[DataT],
         entry: NbrT,
         ef_search: usize,
+        filter: F,
     ) -> ClosestQueue<NbrT> {
         let mut visited: HashSet<NbrT> = HashSet::new();
         let mut candidates: ClosestQueue<NbrT> = BinaryHeap::new();
@@ -52,13 +108,14 @@ where
         let dist = self.dist(query, self.graph.data(entry.as_()));
         visited.insert(entry);
         candidates.push(Closest { node: entry, dist });
-        worklist.push(Furthest { node: entry, dist });
+        if filter(entry.as_()) {
+            worklist.push(Furthest { node: entry, dist });
+        }
 
         while !candidates.is_empty() {
             let best_candidate = candidates.pop().unwrap();
-            let worst_nbr = worklist.peek().unwrap();
 
-            if best_candidate.dist > worst_nbr.dist {
+            if worklist.len() >= ef_search && best_candidate.dist > worklist.peek().unwrap().dist {
                 break;
             }
 
@@ -67,9 +124,11 @@ where
                     visited.insert(nbr);
                     let dist = self.dist(query, self.graph.data(nbr.as_()));
                     candidates.push(Closest { node: nbr, dist });
-                    worklist.push(Furthest { node: nbr, dist });
-                    if worklist.len() > ef_search {
-                        worklist.pop();
+                    if filter(nbr.as_()) {
+                        worklist.push(Furthest { node: nbr, dist });
+                        if worklist.len() > ef_search {
+                            worklist.pop();
+                        }
                     }
                 }
             }
@@ -85,36 +144,98 @@ where
         return queue;
     }
 
-    fn select_neighbors(&self, mut candidates: ClosestQueue<NbrT>) -> Vec<NbrT> {
-        if candidates.len() < self.graph.max_nbrs() {
-            let mut selected: Vec<NbrT> = candidates.drain().map(|x| x.node).collect();
-            selected.resize(self.graph.max_nbrs(), NbrT::max_value());
-            return selected;
+    // Searches only the n_matches nodes accepted by the filter. When few nodes
+    // match, the graph search would have to visit most of the graph to find
+    // them, so the matching nodes are scanned exhaustively instead. candidates
+    // are the nodes that are scanned, which must include every matching node.
+    fn subset_search<F: Fn(usize) -> bool, I: Iterator<Item = usize>>(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        n_matches: usize,
+        candidates: I,
+        filter: F,
+    ) -> Result<ClosestQueue<NbrT>> {
+        if n_matches > ef_search.max(self.graph.len() / 100) {
+            let entry = self.search_initialization(query, 100);
+            return Ok(self.filtered_beam_search(query, Self::node_id(entry)?, ef_search, filter));
         }
 
-        let mut selected: Vec<NbrT> = Vec::with_capacity(self.graph.max_nbrs());
-
-        while candidates.len() > 0 && selected.len() < self.graph.max_nbrs() {
-            let best_candidate = candidates.pop().unwrap();
+        let mut worklist: FurthestQueue<NbrT> = BinaryHeap::with_capacity(ef_search + 1);
+        for node in candidates.filter(|&node| filter(node)) {
+            let dist = self.dist(query, self.graph.data(node));
+            worklist.push(Furthest {
+                node: Self::node_id(node)?,
+                dist,
+            });
+            if worklist.len() > ef_search {
+                worklist.pop();
+            }
+        }
 
-            let mut keep = true;
+        let mut queue: ClosestQueue<NbrT> = BinaryHeap::with_capacity(worklist.len());
+        worklist.drain().for_each(|x| {
+            queue.push(Closest {
+                node: x.node,
+                dist: x.dist,
+            })
+        });
+        return Ok(queue);
+    }
 
-            for &choosen_nbr in &selected {
-                let dist = self.dist(
-                    self.graph.data(best_candidate.node.as_()),
-                    self.graph.data(choosen_nbr.as_()),
-                );
-                if dist < best_candidate.dist {
-                    keep = false;
-                }
-            }
+    // Beam search from the best initialization that skips removed nodes.
+    fn search(&self, query: &[DataT], ef_search: usize) -> Result<ClosestQueue<NbrT>> {
+        let entry = Self::node_id(self.search_initialization(query, 100))?;
+        if self.graph.n_removed() == 0 {
+            return Ok(self.beam_search(query, entry, ef_search));
+        }
+        return Ok(
+            self.filtered_beam_search(query, entry, ef_search, |node| !self.graph.is_removed(node))
+        );
+    }
 
-            if keep {
-                selected.push(best_candidate.node);
-            }
+    fn top_results(&self, mut results: ClosestQueue<NbrT>, topk: usize) -> Vec<(LabelT, f32)> {
+        let mut output = Vec::with_capacity(topk);
+        while output.len() < topk && !results.is_empty() {
+            let best = results.pop().unwrap();
+            output.push((self.graph.label(best.node.as_()), best.dist));
         }
+        return output;
+    }
 
-        selected.resize(self.graph.max_nbrs(), NbrT::max_value());
+    fn select_neighbors(&self, candidates: ClosestQueue<NbrT>) -> Vec<NbrT> {
+        return search::select_neighbors(&self.graph, &self.distance_fn, candidates);
+    }
+
+    // Most of the neighbors of a node are chosen within its namespace so that
+    // searches restricted to the namespace stay well connected, while a quarter
+    // of the edges are reserved for the closest nodes overall so that the
+    // graph as a whole remains navigable.
+    fn select_namespace_neighbors(
+        &self,
+        local: ClosestQueue<NbrT>,
+        global: ClosestQueue<NbrT>,
+    ) -> Vec<NbrT> {
+        let max_nbrs = self.graph.max_nbrs();
+
+        let local: Vec<NbrT> = self
+            .select_neighbors(local)
+            .into_iter()
+            .filter(|&nbr| nbr != NbrT::max_value())
+            .collect();
+        let global: Vec<NbrT> = self
+            .select_neighbors(global)
+            .into_iter()
+            .filter(|&nbr| nbr != NbrT::max_value() && !local.contains(&nbr))
+            .collect();
+
+        let n_local = local.len().min(max_nbrs - max_nbrs / 4);
+        let mut selected = local[..n_local].to_vec();
+        selected.extend(global.iter().take(max_nbrs - n_local));
+        let n_remaining = max_nbrs - selected.len();
+        selected.extend(local[n_local..].iter().take(n_remaining));
+
+        selected.resize(max_nbrs, NbrT::max_value());
         return selected;
     }
 
@@ -124,42 +245,31 @@ where
                 continue;
             }
 
-            let mut added = false;
-
-            for nbr_nbr in self.graph.nbrs_mut(nbr.as_()) {
-                if *nbr_nbr == NbrT::max_value() {
-                    *nbr_nbr = new_node;
-                    added = true;
-                    break;
-                }
-            }
-
-            if !added {
-                let mut candidates: ClosestQueue<NbrT> =
-                    BinaryHeap::with_capacity(self.graph.max_nbrs() + 1);
-
-                let nbr_data = self.graph.data(nbr.as_());
-
-                candidates.push(Closest {
-                    node: new_node,
-                    dist: self.dist(new_data, nbr_data),
-                });
-
-                for &nbr_nbr in self.graph.nbrs(nbr.as_()) {
-                    candidates.push(Closest {
-                        node: nbr_nbr,
-                        dist: self.dist(self.graph.data(nbr_nbr.as_()), nbr_data),
-                    });
-                }
-
-                let selected: Vec<NbrT> = self.select_neighbors(candidates);
-                self.graph.nbrs_mut(nbr.as_()).copy_from_slice(&selected);
-            }
+            let mut nbr_nbrs = self.graph.nbrs(nbr.as_()).to_vec();
+            search::add_neighbor(
+                &self.graph,
+                &self.distance_fn,
+                nbr.as_(),
+                &mut nbr_nbrs,
+                new_node,
+                new_data,
+            );
+            self.graph.nbrs_mut(nbr.as_()).copy_from_slice(&nbr_nbrs);
         }
     }
 
     fn search_initialization(&self, query: &[DataT], num_initializations: usize) -> usize {
-        let mut step_size = self.graph.len() / num_initializations;
+        return self.search_initialization_in(query, self.graph.len(), num_initializations);
+    }
+
+    // Picks the entry node among the first n_nodes nodes only.
+    fn search_initialization_in(
+        &self,
+        query: &[DataT],
+        n_nodes: usize,
+        num_initializations: usize,
+    ) -> usize {
+        let mut step_size = n_nodes / num_initializations;
         if step_size < 1 {
             step_size = 1;
         }
@@ -167,7 +277,7 @@ where
         let mut min_dist = f32::MAX;
         let mut entry_node = 0;
 
-        for node in (0..self.graph.len()).step_by(step_size) {
+        for node in (0..n_nodes).step_by(step_size) {
             let dist = self.dist(query, self.graph.data(node));
             if dist < min_dist {
                 min_dist = dist;
@@ -178,44 +288,431 @@ where
         return entry_node;
     }
 
-    pub fn insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) {
-        if self.graph.len() == 0 {
+    // Largest number of nodes that can be addressed with NbrT, inserts beyond it
+    // fail with Error::CapacityOverflow.
+    pub fn max_nodes() -> usize {
+        // NbrT::max_value() is reserved as the empty neighbor sentinel.
+        return NbrT::max_value().as_();
+    }
+
+    fn node_id(node: usize) -> Result<NbrT> {
+        if node >= Self::max_nodes() {
+            return Err(Error::CapacityOverflow {
+                len: node,
+                max: Self::max_nodes(),
+            });
+        }
+        return NbrT::from_usize(node).ok_or(Error::CapacityOverflow {
+            len: node,
+            max: Self::max_nodes(),
+        });
+    }
+
+    fn check_dim(&self, data: &[DataT]) -> Result<()> {
+        if data.len() != self.graph.data_dim() {
+            return Err(Error::DimensionMismatch {
+                expected: self.graph.data_dim(),
+                got: data.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    pub fn try_insert(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_with_payload(label, data, &[], ef_construction);
+    }
+
+    pub fn try_insert_with_payload(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_in_namespace(0, label, data, payload, ef_construction);
+    }
+
+    pub fn try_insert_in_namespace(
+        &mut self,
+        namespace: u32,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        ef_construction: usize,
+    ) -> Result<()> {
+        self.check_dim(data)?;
+        let new_node = Self::node_id(self.graph.len())?;
+
+        if self.graph.is_empty() {
             let mut neighbors = Vec::with_capacity(self.graph.max_nbrs());
             neighbors.resize(self.graph.max_nbrs(), NbrT::max_value());
-            self.graph.add_node(label, &neighbors, data);
-            return;
+            self.graph
+                .add_node_in_namespace(namespace, label, &neighbors, data, payload)?;
+            self.add_to_namespace(namespace, new_node, false);
+            return Ok(());
         }
 
         let entry = self.search_initialization(data, 100);
 
-        let candidates = self.beam_search(data, NbrT::from(entry).unwrap(), ef_construction);
-        let neighbors = self.select_neighbors(candidates);
+        let candidates = self.beam_search(data, Self::node_id(entry)?, ef_construction);
+        let namespace_len = self.namespace_len(namespace);
+        let shared = self
+            .namespace_lens
+            .iter()
+            .any(|(&other, &len)| other != namespace && len > 0);
+        let neighbors = if namespace_len == 0 || !shared {
+            self.select_neighbors(candidates)
+        } else {
+            let local = self.subset_search(
+                data,
+                ef_construction,
+                namespace_len,
+                self.namespace_nodes(namespace),
+                |node| self.graph.namespace(node) == namespace,
+            )?;
+            self.select_namespace_neighbors(local, candidates)
+        };
+
+        self.graph
+            .add_node_in_namespace(namespace, label, &neighbors, data, payload)?;
+        self.add_to_namespace(namespace, new_node, false);
 
-        let new_node = self.graph.add_node(label, &neighbors, data);
+        self.connect_neighbors(neighbors, new_node, data);
 
-        self.connect_neighbors(neighbors, NbrT::from_usize(new_node).unwrap(), data);
+        return Ok(());
     }
 
-    pub fn query(&self, query: &[DataT], ef_search: usize, topk: usize) -> Vec<(LabelT, f32)> {
-        if self.graph.len() == 0 {
-            return Vec::new();
+    pub fn insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) {
+        if let Err(e) = self.try_insert(label, data, ef_construction) {
+            panic!("{}", e);
         }
+    }
 
-        let entry = self.search_initialization(query, 100);
+    pub fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+
+        if self.graph.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
 
-        let mut results = self.beam_search(query, NbrT::from(entry).unwrap(), ef_search);
+        let results = self.search(query, ef_search)?;
+
+        return Ok(self.top_results(results, topk));
+    }
+
+    pub fn try_query_with_payload(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32, &[u8])>> {
+        self.check_dim(query)?;
+
+        if self.graph.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
+
+        let mut results = self.search(query, ef_search)?;
 
         let mut output = Vec::with_capacity(topk);
         while output.len() < topk && !results.is_empty() {
             let best = results.pop().unwrap();
-            output.push((self.graph.label(best.node.as_()), best.dist));
+            let node = best.node.as_();
+            output.push((self.graph.label(node), best.dist, self.graph.payload(node)));
         }
 
-        return output;
+        return Ok(output);
     }
 
-    pub fn reorder(&mut self, reordering: &dyn Reordering) {
-        let out_nodes = (0..self.len())
+    // Returns the closest nodes for which filter(label, payload) is true.
+    pub fn try_query_filtered<F: Fn(LabelT, &[u8]) -> bool>(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+        filter: F,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+
+        if self.graph.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
+
+        let entry = self.search_initialization(query, 100);
+
+        let results = self.filtered_beam_search(query, Self::node_id(entry)?, ef_search, |node| {
+            !self.graph.is_removed(node) && filter(self.graph.label(node), self.graph.payload(node))
+        });
+
+        return Ok(self.top_results(results, topk));
+    }
+
+    pub fn try_insert_with_attributes(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        attributes: &Attributes,
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_with_payload(label, data, &attributes.encode()?, ef_construction);
+    }
+
+    // Returns the closest nodes whose attributes match the filter. The number
+    // of matching nodes is estimated from a sample. When few nodes match, the
+    // graph search would have to visit most of the graph to find them, so the
+    // nodes are scanned exhaustively instead. Otherwise the filter is only
+    // evaluated for the nodes visited by the graph search.
+    pub fn try_query_with_filter(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+        filter: &Filter,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+
+        if self.graph.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
+
+        let matches =
+            |node: usize| !self.graph.is_removed(node) && filter.matches(self.graph.payload(node));
+
+        let n_nodes = self.graph.len();
+        let step = (n_nodes / FILTER_SAMPLE_SIZE).max(1);
+        let n_sampled = n_nodes.div_ceil(step);
+        let n_sample_matches = (0..n_nodes)
+            .step_by(step)
+            .filter(|&node| matches(node))
+            .count();
+        let n_matches = n_sample_matches * n_nodes / n_sampled;
+
+        let results = self.subset_search(query, ef_search, n_matches, 0..n_nodes, matches)?;
+
+        return Ok(self.top_results(results, topk));
+    }
+
+    // Returns the closest nodes within the namespace.
+    pub fn try_query_in_namespace(
+        &self,
+        namespace: u32,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+
+        if self.graph.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
+
+        let namespace_len = self.namespace_len(namespace);
+        if namespace_len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let results = self.subset_search(
+            query,
+            ef_search,
+            namespace_len,
+            self.namespace_nodes(namespace),
+            |node| self.graph.namespace(node) == namespace && !self.graph.is_removed(node),
+        )?;
+
+        return Ok(self.top_results(results, topk));
+    }
+
+    pub fn namespace_len(&self, namespace: u32) -> usize {
+        return self.namespace_lens.get(&namespace).copied().unwrap_or(0);
+    }
+
+    fn namespace_nodes(&self, namespace: u32) -> impl Iterator<Item = usize> + '_ {
+        return self
+            .namespace_nodes
+            .get(&namespace)
+            .into_iter()
+            .flatten()
+            .map(|node| node.as_());
+    }
+
+    fn add_to_namespace(&mut self, namespace: u32, node: NbrT, removed: bool) {
+        self.namespace_nodes
+            .entry(namespace)
+            .or_default()
+            .push(node);
+        if !removed {
+            *self.namespace_lens.entry(namespace).or_insert(0) += 1;
+        }
+    }
+
+    // Rebuilds the namespace sizes and node lists, for a graph that was loaded
+    // or whose node ids changed.
+    fn index_namespaces(&mut self) {
+        self.namespace_lens.clear();
+        self.namespace_nodes.clear();
+        for node in 0..self.graph.len() {
+            let node_id = NbrT::from_usize(node).unwrap();
+            self.add_to_namespace(
+                self.graph.namespace(node),
+                node_id,
+                self.graph.is_removed(node),
+            );
+        }
+    }
+
+    pub fn namespace_stats(&self) -> BTreeMap<u32, NamespaceStats> {
+        let mut stats: BTreeMap<u32, NamespaceStats> = BTreeMap::new();
+        for node in 0..self.graph.len() {
+            if self.graph.is_removed(node) {
+                continue;
+            }
+            let namespace = self.graph.namespace(node);
+            let entry = stats.entry(namespace).or_default();
+            entry.len += 1;
+            for &nbr in self.graph.nbrs(node) {
+                if nbr != NbrT::max_value() {
+                    entry.edges += 1;
+                    if self.graph.namespace(nbr.as_()) == namespace {
+                        entry.intra_edges += 1;
+                    }
+                }
+            }
+        }
+        return stats;
+    }
+
+    // Removes all vectors with the label and returns whether there were any.
+    // Removed nodes stay in the graph to keep it connected but are no longer
+    // returned by queries.
+    pub fn remove(&mut self, label: LabelT) -> bool {
+        return self.remove_before(label, self.graph.len());
+    }
+
+    // Removes the nodes with the label among the first end nodes.
+    pub(super) fn remove_before(&mut self, label: LabelT, end: usize) -> bool {
+        let mut found = false;
+        for node in 0..end {
+            if self.graph.label(node) == label && self.graph.remove(node) {
+                *self
+                    .namespace_lens
+                    .get_mut(&self.graph.namespace(node))
+                    .unwrap() -= 1;
+                found = true;
+            }
+        }
+        return found;
+    }
+
+    // Appends the nodes of other, keeping their edges, and links the two graphs
+    // together. Up to n_samples of the appended nodes, evenly spaced, are
+    // searched for in the original graph and their closest original nodes are
+    // added to their neighbors, with edges back from those nodes. Linking every
+    // appended node gives the best recall, while a sample is much faster for
+    // large indexes.
+    pub fn merge(&mut self, other: &Self, ef_construction: usize, n_samples: usize) -> Result<()> {
+        if other.graph.data_dim() != self.graph.data_dim() {
+            return Err(Error::DimensionMismatch {
+                expected: self.graph.data_dim(),
+                got: other.graph.data_dim(),
+            });
+        }
+        if other.graph.max_nbrs() != self.graph.max_nbrs() {
+            return Err(Error::NeighborCountMismatch {
+                expected: self.graph.max_nbrs(),
+                got: other.graph.max_nbrs(),
+            });
+        }
+        if other.graph.is_empty() {
+            return Ok(());
+        }
+
+        let offset = self.graph.len();
+        Self::node_id(offset + other.graph.len() - 1)?;
+
+        let mut nbrs = Vec::with_capacity(self.graph.max_nbrs());
+        for node in 0..other.graph.len() {
+            nbrs.clear();
+            for &nbr in other.graph.nbrs(node) {
+                if nbr == NbrT::max_value() {
+                    nbrs.push(nbr);
+                } else {
+                    nbrs.push(Self::node_id(nbr.as_() + offset)?);
+                }
+            }
+            let namespace = other.graph.namespace(node);
+            let new_node = self.graph.add_node_in_namespace(
+                namespace,
+                other.graph.label(node),
+                &nbrs,
+                other.graph.data(node),
+                other.graph.payload(node),
+            )?;
+            let removed = other.graph.is_removed(node);
+            if removed {
+                self.graph.remove(new_node);
+            }
+            self.add_to_namespace(namespace, Self::node_id(new_node)?, removed);
+        }
+
+        if offset == 0 || n_samples == 0 {
+            return Ok(());
+        }
+
+        let step = other.graph.len().div_ceil(n_samples);
+        for node in (offset..self.graph.len()).step_by(step) {
+            let data = self.graph.data(node).to_vec();
+            let new_node = Self::node_id(node)?;
+
+            let entry = self.search_initialization_in(&data, offset, 100);
+            let mut candidates =
+                self.filtered_beam_search(&data, Self::node_id(entry)?, ef_construction, |x| {
+                    x < offset
+                });
+            let linked: Vec<NbrT> = candidates.iter().map(|x| x.node).collect();
+            for &nbr in self.graph.nbrs(node) {
+                if nbr != NbrT::max_value() {
+                    candidates.push(Closest {
+                        node: nbr,
+                        dist: self.dist(&data, self.graph.data(nbr.as_())),
+                    });
+                }
+            }
+
+            let neighbors = self.select_neighbors(candidates);
+            self.graph.nbrs_mut(node).copy_from_slice(&neighbors);
+
+            // Only the original nodes need an edge back, the appended ones
+            // already have theirs.
+            let neighbors = neighbors
+                .into_iter()
+                .filter(|nbr| linked.contains(nbr))
+                .collect();
+            self.connect_neighbors(neighbors, new_node, &data);
+        }
+
+        return Ok(());
+    }
+
+    pub fn query(&self, query: &[DataT], ef_search: usize, topk: usize) -> Vec<(LabelT, f32)> {
+        match self.try_query(query, ef_search, topk) {
+            Ok(results) => results,
+            Err(Error::EmptyIndex) => Vec::new(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn out_nodes(&self) -> Vec<Vec<usize>> {
+        return (0..self.graph.len())
             .map(|node| {
                 self.graph
                     .nbrs(node)
@@ -225,19 +722,142 @@ where
                     .collect::<Vec<_>>()
             })
             .collect();
+    }
+
+    pub fn try_reorder(&mut self, reordering: &dyn Reordering) -> Result<()> {
+        let perm = reordering.reorder(&self.out_nodes())?;
+
+        return self.apply_permutation(&perm);
+    }
+
+    pub fn apply_permutation(&mut self, perm: &[usize]) -> Result<()> {
+        reordering::validate_permutation(perm, self.graph.len())?;
+
+        self.graph.reorder(perm);
+        self.index_namespaces();
+
+        return Ok(());
+    }
+
+    pub fn reorder(&mut self, reordering: &dyn Reordering) {
+        if let Err(e) = self.try_reorder(reordering) {
+            panic!("{}", e);
+        }
+    }
+
+    // Degree distributions and connectivity of the graph. Nodes are counted as
+    // unreachable when no search can get to them from any of the nodes that
+    // are considered as entry points.
+    pub fn graph_stats(&self) -> GraphStats {
+        let n_nodes = self.graph.len();
+        let entries: Vec<usize> = (0..n_nodes).step_by((n_nodes / 100).max(1)).collect();
+        return graph_stats::compute(
+            &self.out_nodes(),
+            self.graph.max_nbrs(),
+            &entries,
+            |a, b| self.dist(self.graph.data(a), self.graph.data(b)),
+        );
+    }
 
-        let perm = reordering.reorder(&out_nodes);
+    pub fn reordering_metrics(&self, w: usize) -> Result<reordering::metrics::Metrics> {
+        let identity: Vec<usize> = (0..self.graph.len()).collect();
+        return reordering::metrics::compute(&self.out_nodes(), &identity, w);
+    }
 
-        self.graph.reorder(&perm);
+    pub fn save<W: std::io::Write>(&self, writer: &mut W) -> Result<()> {
+        return self.graph.save(writer);
     }
 
+    pub fn load<R: std::io::Read>(reader: &mut R, distance_fn: DistanceFn) -> Result<Self> {
+        let graph = InMemStorage::<NbrT, DataT>::load(reader)?;
+        if graph.max_nbrs() == 0 {
+            return Err(Error::InvalidFormat(
+                "file contains a flat index, not a graph index".to_string(),
+            ));
+        }
+        if graph.len() > Self::max_nodes() {
+            return Err(Error::CapacityOverflow {
+                len: graph.len(),
+                max: Self::max_nodes(),
+            });
+        }
+        // Searches index arrays by neighbor id, so ids past the end must not
+        // get into the graph even if the file is otherwise intact.
+        if let Some(&(node, nbr)) = Self::verify_graph(&graph).out_of_range.first() {
+            return Err(Error::InvalidGraph(format!(
+                "node {} has neighbor {} but there are {} nodes",
+                node,
+                nbr,
+                graph.len()
+            )));
+        }
+        let mut index = Self {
+            graph,
+            distance_fn,
+            namespace_lens: HashMap::new(),
+            namespace_nodes: HashMap::new(),
+        };
+        index.index_namespaces();
+        return Ok(index);
+    }
+
+    // Checks that every neighbor id is either empty or a node of the graph,
+    // and that no node links to itself or lists a neighbor twice.
+    pub fn verify(&self) -> VerifyReport {
+        return Self::verify_graph(&self.graph);
+    }
+
+    fn verify_graph(graph: &InMemStorage<NbrT, DataT>) -> VerifyReport {
+        let mut report = VerifyReport {
+            n_nodes: graph.len(),
+            ..Default::default()
+        };
+        let mut nbrs = Vec::with_capacity(graph.max_nbrs());
+        for node in 0..graph.len() {
+            nbrs.clear();
+            for &nbr in graph.nbrs(node) {
+                if nbr == NbrT::max_value() {
+                    continue;
+                }
+                let nbr: usize = nbr.as_();
+                report.n_edges += 1;
+                if nbr >= graph.len() {
+                    report.out_of_range.push((node, nbr));
+                } else if nbr == node {
+                    report.self_loops.push(node);
+                }
+                nbrs.push(nbr);
+            }
+            nbrs.sort_unstable();
+            for (i, pair) in nbrs.windows(2).enumerate() {
+                if pair[0] == pair[1] && (i == 0 || nbrs[i - 1] != pair[0]) {
+                    report.duplicate_edges.push((node, pair[0]));
+                }
+            }
+        }
+        return report;
+    }
+
+    // Number of vectors, not counting removed ones.
     pub fn len(&self) -> usize {
-        self.graph.len()
+        self.graph.len() - self.graph.n_removed()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn data_dim(&self) -> usize {
         self.graph.data_dim()
     }
+
+    pub fn max_nbrs(&self) -> usize {
+        self.graph.max_nbrs()
+    }
+
+    pub(super) fn storage(&self) -> &InMemStorage<NbrT, DataT> {
+        &self.graph
+    }
 }
 
 #[cfg(test)]
@@ -245,7 +865,7 @@ mod tests {
     use super::*;
     use crate::flatnav::distance::EuclideanDistance;
     use crate::flatnav::reordering;
-    use rand::Rng;
+    use rand::{Rng, SeedableRng};
 
     #[test]
     fn test_index() {
@@ -291,4 +911,373 @@ mod tests {
             assert_eq!(results[0].0, idx as u64);
         }
     }
+
+    #[test]
+    fn test_index_verify() {
+        let mut index = Index::<u32, f32, EuclideanDistance>::new(4, 1, 20, EuclideanDistance);
+        for i in 0..20 {
+            index.insert(i, &[i as f32], 16);
+        }
+        let report = index.verify();
+        assert!(report.is_ok());
+        assert_eq!(report.n_nodes, 20);
+        assert!(report.n_edges > 20);
+
+        index.graph.nbrs_mut(3).copy_from_slice(&[3, 7, 7, 7]);
+        index
+            .graph
+            .nbrs_mut(5)
+            .copy_from_slice(&[6, 20, u32::MAX, 4]);
+        let report = index.verify();
+        assert!(!report.is_ok());
+        assert_eq!(report.self_loops, vec![3]);
+        assert_eq!(report.duplicate_edges, vec![(3, 7)]);
+        assert_eq!(report.out_of_range, vec![(5, 20)]);
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        assert!(matches!(
+            Index::<u32, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance),
+            Err(Error::InvalidGraph(_))
+        ));
+    }
+
+    #[test]
+    fn test_index_graph_stats() {
+        let mut index = Index::<u32, f32, EuclideanDistance>::new(4, 1, 50, EuclideanDistance);
+        for i in 0..50 {
+            index.insert(i, &[i as f32], 16);
+        }
+        let stats = index.graph_stats();
+        assert_eq!(stats.n_nodes, 50);
+        assert_eq!(stats.out_degrees.len(), 5);
+        assert_eq!(stats.out_degrees.iter().sum::<usize>(), 50);
+        assert_eq!(stats.in_degrees.iter().sum::<usize>(), 50);
+        assert_eq!(stats.n_components, 1);
+        assert_eq!(stats.n_unreachable, 0);
+        assert!(stats.avg_edge_length > 0.0);
+    }
+
+    #[test]
+    fn test_index_errors() {
+        let mut index = Index::<u8, f32, EuclideanDistance>::new(4, 2, 300, EuclideanDistance);
+
+        assert_eq!(index.try_query(&[0.0, 0.0], 16, 1), Err(Error::EmptyIndex));
+        assert_eq!(
+            index.try_insert(0, &[0.0, 0.0, 0.0], 16),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                got: 3
+            })
+        );
+
+        for i in 0..255 {
+            index.try_insert(i, &[i as f32, 0.0], 16).unwrap();
+        }
+        assert_eq!(
+            index.try_insert(255, &[255.0, 0.0], 16),
+            Err(Error::CapacityOverflow { len: 255, max: 255 })
+        );
+        assert_eq!(index.len(), 255);
+
+        assert_eq!(
+            index.try_query(&[0.0], 16, 1),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                got: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_index_nbr_types() {
+        assert_eq!(Index::<u16, f32, EuclideanDistance>::max_nodes(), 65535);
+        assert_eq!(
+            Index::<u64, f32, EuclideanDistance>::max_nodes(),
+            usize::MAX
+        );
+
+        let mut index = Index::<u64, f32, EuclideanDistance>::new(4, 1, 0, EuclideanDistance);
+        for i in 0..100 {
+            index.insert(i, &[i as f32], 16);
+        }
+        assert_eq!(index.query(&[41.9], 16, 1)[0].0, 42);
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let loaded =
+            Index::<u64, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance)
+                .unwrap();
+        assert_eq!(loaded.query(&[41.9], 16, 1)[0].0, 42);
+        assert!(matches!(
+            Index::<u16, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_index_merge() {
+        const DATA_DIM: usize = 16;
+        const N_VECS: usize = 1000;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let data_dist = rand::distr::Uniform::new(-1.0, 1.0).unwrap();
+        let dataset: Vec<Vec<f32>> = (0..N_VECS)
+            .map(|_| (0..DATA_DIM).map(|_| rng.sample(data_dist)).collect())
+            .collect();
+
+        let new_index =
+            || Index::<u32, f32, EuclideanDistance>::new(8, DATA_DIM, 0, EuclideanDistance);
+        let mut a = new_index();
+        let mut b = new_index();
+        for (i, vec) in dataset.iter().enumerate() {
+            if i < N_VECS / 2 {
+                a.insert(i as u64, vec, 32);
+            } else {
+                b.try_insert_in_namespace(1, i as u64, vec, &i.to_le_bytes(), 32)
+                    .unwrap();
+            }
+        }
+        b.remove(N_VECS as u64 - 1);
+
+        a.merge(&b, 32, usize::MAX).unwrap();
+        assert_eq!(a.len(), N_VECS - 1);
+        assert_eq!(a.namespace_len(1), N_VECS / 2 - 1);
+
+        let mut found = 0;
+        for (i, vec) in dataset.iter().enumerate().take(N_VECS - 1) {
+            let results = a.try_query_with_payload(vec, 32, 1).unwrap();
+            if results[0].0 == i as u64 {
+                found += 1;
+                if i >= N_VECS / 2 {
+                    assert_eq!(results[0].2, &i.to_le_bytes()[..]);
+                }
+            }
+        }
+        assert!(found >= 990, "found {} of {}", found, N_VECS - 1);
+        assert!(a.query(&dataset[N_VECS - 1], 32, 1)[0].0 != N_VECS as u64 - 1);
+
+        assert!(matches!(
+            a.merge(
+                &Index::new(4, DATA_DIM, 0, EuclideanDistance),
+                32,
+                usize::MAX
+            ),
+            Err(Error::NeighborCountMismatch { .. })
+        ));
+        assert!(matches!(
+            a.merge(&Index::new(8, 2, 0, EuclideanDistance), 32, usize::MAX),
+            Err(Error::DimensionMismatch { .. })
+        ));
+    }
+
+    struct Reverse;
+
+    impl Reordering for Reverse {
+        fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+            return Ok((0..out_nodes.len()).rev().collect());
+        }
+    }
+
+    struct Constant;
+
+    impl Reordering for Constant {
+        fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+            return Ok(vec![0; out_nodes.len()]);
+        }
+    }
+
+    #[test]
+    fn test_index_permutation() {
+        let mut index = Index::<u32, f32, EuclideanDistance>::new(4, 1, 8, EuclideanDistance);
+        for i in 0..8 {
+            index.insert(i, &[i as f32], 16);
+        }
+
+        assert!(matches!(
+            index.try_reorder(&Constant),
+            Err(Error::InvalidPermutation(_))
+        ));
+        assert!(matches!(
+            index.apply_permutation(&[0, 1, 2]),
+            Err(Error::InvalidPermutation(_))
+        ));
+        for node in 0..8 {
+            assert_eq!(index.graph.label(node), node as u64);
+        }
+
+        index.try_reorder(&Reverse).unwrap();
+        for node in 0..8 {
+            assert_eq!(index.graph.label(node), 7 - node as u64);
+            assert_eq!(index.graph.data(node), &[(7 - node) as f32]);
+        }
+
+        let results = index.query(&[3.1], 16, 1);
+        assert_eq!(results[0].0, 3);
+
+        let metrics = index.reordering_metrics(2).unwrap();
+        assert!(metrics.bandwidth < 8);
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let loaded =
+            Index::<u32, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance)
+                .unwrap();
+        assert_eq!(loaded.len(), 8);
+        assert_eq!(loaded.query(&[3.1], 16, 1)[0].0, 3);
+
+        let mut copy = loaded.clone();
+        copy.insert(8, &[8.0], 16);
+        assert_eq!(copy.len(), 9);
+        assert_eq!(loaded.len(), 8);
+    }
+
+    #[test]
+    fn test_index_payloads() {
+        let mut index = Index::<u32, f32, EuclideanDistance>::new(4, 1, 100, EuclideanDistance);
+        for i in 0..100 {
+            let payload = if i % 2 == 0 { "even" } else { "odd" };
+            index
+                .try_insert_with_payload(i, &[i as f32], payload.as_bytes(), 16)
+                .unwrap();
+        }
+
+        let results = index.try_query_with_payload(&[10.1], 16, 2).unwrap();
+        assert_eq!(results[0], (10, results[0].1, "even".as_bytes()));
+        assert_eq!(results[1], (11, results[1].1, "odd".as_bytes()));
+
+        let results = index
+            .try_query_filtered(&[10.1], 16, 3, |_, payload| payload == b"odd")
+            .unwrap();
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels, vec![11, 9, 13]);
+
+        let results = index
+            .try_query_filtered(&[10.1], 16, 3, |label, _| label >= 90)
+            .unwrap();
+        assert_eq!(results[0].0, 90);
+
+        index.try_reorder(&Reverse).unwrap();
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let loaded =
+            Index::<u32, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance)
+                .unwrap();
+        let results = loaded.try_query_with_payload(&[41.1], 16, 1).unwrap();
+        assert_eq!(results[0].0, 41);
+        assert_eq!(results[0].2, b"odd");
+    }
+
+    #[test]
+    fn test_index_filter() {
+        let mut index = Index::<u32, f32, EuclideanDistance>::new(8, 1, 1000, EuclideanDistance);
+        for i in 0..1000 {
+            let mut attributes = Attributes::new();
+            attributes.push("category", format!("c{}", i % 10));
+            attributes.push("year", i as i64);
+            index
+                .try_insert_with_attributes(i, &[i as f32], &attributes, 32)
+                .unwrap();
+        }
+        index.try_insert(1000, &[500.0], 32).unwrap();
+
+        // Selective enough to use the graph search.
+        let filter = Filter::eq("category", "c3");
+        let results = index
+            .try_query_with_filter(&[500.0], 32, 3, &filter)
+            .unwrap();
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels[0], 503);
+        assert!(labels.contains(&493) && labels.contains(&513));
+
+        // Few matches, scanned exhaustively.
+        let filter = Filter::And(vec![
+            Filter::range("year", 100, 110),
+            Filter::Not(Box::new(Filter::is_in("category", vec!["c5", "c6"]))),
+        ]);
+        let results = index
+            .try_query_with_filter(&[500.0], 32, 4, &filter)
+            .unwrap();
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels, vec![110, 109, 108, 107]);
+
+        let filter = Filter::eq("category", "missing");
+        assert!(index
+            .try_query_with_filter(&[500.0], 32, 4, &filter)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_index_namespaces() {
+        const DATA_DIM: usize = 8;
+        const N_VECS: usize = 3000;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let data_dist = rand::distr::Uniform::new(-1.0, 1.0).unwrap();
+        let dataset: Vec<Vec<f32>> = (0..N_VECS)
+            .map(|_| (0..DATA_DIM).map(|_| rng.sample(data_dist)).collect())
+            .collect();
+
+        // Namespace 2 is much smaller than the others.
+        let namespace = |i: usize| {
+            if i.is_multiple_of(100) {
+                2
+            } else {
+                (i % 2) as u32
+            }
+        };
+
+        let mut index =
+            Index::<u32, f32, EuclideanDistance>::new(8, DATA_DIM, N_VECS, EuclideanDistance);
+        for (i, data) in dataset.iter().enumerate() {
+            index
+                .try_insert_in_namespace(namespace(i), i as u64, data, &[], 32)
+                .unwrap();
+        }
+
+        assert_eq!(index.namespace_len(0), 1470);
+        assert_eq!(index.namespace_len(2), 30);
+        let stats = index.namespace_stats();
+        assert_eq!(stats.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(stats[&1].len, 1500);
+        assert!(stats[&1].intra_edges * 2 > stats[&1].edges);
+
+        index.try_reorder(&reordering::GOrder::new(5)).unwrap();
+        let small: Vec<usize> = index.namespace_nodes(2).collect();
+        assert_eq!(small.len(), 30);
+        assert!(small.iter().all(|&node| index.graph.namespace(node) == 2));
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let index =
+            Index::<u32, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance)
+                .unwrap();
+        assert_eq!(index.namespace_stats(), stats);
+
+        let mut hits = 0;
+        for query in dataset.iter().take(100) {
+            for ns in 0..3 {
+                let results = index.try_query_in_namespace(ns, query, 32, 1).unwrap();
+                assert!(results.iter().all(|x| namespace(x.0 as usize) == ns));
+
+                let expected = (0..N_VECS)
+                    .filter(|&i| namespace(i) == ns)
+                    .min_by(|&a, &b| {
+                        let a = EuclideanDistance.call(query, &dataset[a]);
+                        let b = EuclideanDistance.call(query, &dataset[b]);
+                        a.partial_cmp(&b).unwrap()
+                    })
+                    .unwrap();
+                if results[0].0 == expected as u64 {
+                    hits += 1;
+                }
+            }
+        }
+        assert!(hits > 270, "recall@1 {} / 300", hits);
+
+        assert!(index
+            .try_query_in_namespace(3, &dataset[0], 32, 1)
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -0,0 +1,441 @@
+use super::error::{Error, Result};
+use super::storage::read_bytes;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+
+pub trait Element: Copy {
+    const SIZE: usize;
+    const NPY_DESCR: &'static str;
+
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+
+    fn write_le_bytes(&self, out: &mut Vec<u8>);
+}
+
+macro_rules! impl_element {
+    ($t:ty, $descr:expr) => {
+        impl Element for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+            const NPY_DESCR: &'static str = $descr;
+
+            fn from_le_bytes(bytes: &[u8]) -> Self {
+                return <$t>::from_le_bytes(bytes.try_into().unwrap());
+            }
+
+            fn write_le_bytes(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+    };
+}
+
+impl_element!(f32, "<f4");
+impl_element!(f64, "<f8");
+impl_element!(u8, "|u1");
+impl_element!(i8, "|i1");
+impl_element!(i32, "<i4");
+impl_element!(i64, "<i8");
+
+// Reads .fvecs/.bvecs/.ivecs files one vector at a time. Each vector is stored
+// as a little endian i32 dimension followed by that many components.
+pub struct VecsReader<R: Read, T: Element> {
+    reader: R,
+    n_read: usize,
+    _t: PhantomData<T>,
+}
+
+pub type FvecsReader<R> = VecsReader<R, f32>;
+pub type BvecsReader<R> = VecsReader<R, u8>;
+pub type IvecsReader<R> = VecsReader<R, i32>;
+
+impl<R: Read, T: Element> VecsReader<R, T> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            n_read: 0,
+            _t: PhantomData,
+        }
+    }
+
+    fn read_vec(&mut self) -> Result<Option<Vec<T>>> {
+        // Only a clean end of the input between two vectors ends the file.
+        let mut dim = Vec::with_capacity(4);
+        Read::take(&mut self.reader, 4).read_to_end(&mut dim)?;
+        match dim.len() {
+            0 => return Ok(None),
+            4 => {}
+            n => {
+                return Err(Error::InvalidFormat(format!(
+                    "vector {} is truncated: {} of 4 dimension bytes",
+                    self.n_read, n
+                )));
+            }
+        }
+
+        let dim = i32::from_le_bytes(dim.try_into().unwrap());
+        if dim < 0 {
+            return Err(Error::InvalidFormat(format!(
+                "vector {} has negative dimension {}",
+                self.n_read, dim
+            )));
+        }
+
+        // The dimension comes from the file, so the vector is read without
+        // allocating for it up front.
+        let buf = read_bytes(&mut self.reader, dim as usize * T::SIZE).map_err(|e| {
+            Error::InvalidFormat(format!("vector {} is truncated: {}", self.n_read, e))
+        })?;
+        self.n_read += 1;
+
+        return Ok(Some(
+            buf.chunks_exact(T::SIZE).map(T::from_le_bytes).collect(),
+        ));
+    }
+}
+
+impl<T: Element> VecsReader<BufReader<File>, T> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        return Ok(Self::new(BufReader::new(File::open(path)?)));
+    }
+}
+
+impl<R: Read, T: Element> Iterator for VecsReader<R, T> {
+    type Item = Result<Vec<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        return self.read_vec().transpose();
+    }
+}
+
+pub struct VecsWriter<W: Write, T: Element> {
+    writer: W,
+    buf: Vec<u8>,
+    _t: PhantomData<T>,
+}
+
+pub type FvecsWriter<W> = VecsWriter<W, f32>;
+pub type BvecsWriter<W> = VecsWriter<W, u8>;
+pub type IvecsWriter<W> = VecsWriter<W, i32>;
+
+impl<W: Write, T: Element> VecsWriter<W, T> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::new(),
+            _t: PhantomData,
+        }
+    }
+
+    pub fn write(&mut self, vec: &[T]) -> Result<()> {
+        let dim = i32::try_from(vec.len()).map_err(|_| {
+            Error::InvalidFormat(format!("vector dimension {} is too large", vec.len()))
+        })?;
+
+        self.buf.clear();
+        self.buf.extend_from_slice(&dim.to_le_bytes());
+        vec.iter().for_each(|x| x.write_le_bytes(&mut self.buf));
+        self.writer.write_all(&self.buf)?;
+
+        return Ok(());
+    }
+
+    pub fn into_inner(mut self) -> Result<W> {
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+impl<T: Element> VecsWriter<BufWriter<File>, T> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        return Ok(Self::new(BufWriter::new(File::create(path)?)));
+    }
+}
+
+// numpy itself writes headers of a few hundred bytes.
+const MAX_NPY_HEADER_LEN: usize = 1 << 20;
+
+// Reads the rows of a 2 dimensional, C ordered .npy array one at a time.
+pub struct NpyReader<R: Read, T: Element> {
+    reader: R,
+    n_rows: usize,
+    dim: usize,
+    row_size: usize,
+    n_read: usize,
+    _t: PhantomData<T>,
+}
+
+impl<R: Read, T: Element> NpyReader<R, T> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[..6] != b"\x93NUMPY" {
+            return Err(Error::InvalidFormat("not a .npy file".to_string()));
+        }
+
+        let header_len = match magic[6] {
+            1 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                u16::from_le_bytes(len) as usize
+            }
+            2 | 3 => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                u32::from_le_bytes(len) as usize
+            }
+            version => {
+                return Err(Error::InvalidFormat(format!(
+                    "unsupported .npy version {}",
+                    version
+                )));
+            }
+        };
+
+        if header_len > MAX_NPY_HEADER_LEN {
+            return Err(Error::InvalidFormat(format!(
+                ".npy header of {} bytes is too large",
+                header_len
+            )));
+        }
+        let header = read_bytes(&mut reader, header_len)?;
+        let header = String::from_utf8_lossy(&header);
+
+        let descr = header_value(&header, "descr")?;
+        let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+        // Single byte types may be written with either byte order marker.
+        let descr_matches = descr == T::NPY_DESCR
+            || (T::SIZE == 1 && descr.get(1..) == T::NPY_DESCR.get(1..))
+            || (cfg!(target_endian = "little") && descr == T::NPY_DESCR.replace('<', "="));
+        if !descr_matches {
+            return Err(Error::InvalidFormat(format!(
+                "expected dtype {}, got {}",
+                T::NPY_DESCR,
+                descr
+            )));
+        }
+
+        if header_value(&header, "fortran_order")? != "False" {
+            return Err(Error::InvalidFormat(
+                "fortran ordered arrays are not supported".to_string(),
+            ));
+        }
+
+        let shape = header_value(&header, "shape")?;
+        let shape = shape
+            .trim_matches(|c| c == '(' || c == ')')
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                x.parse::<usize>()
+                    .map_err(|_| Error::InvalidFormat(format!("invalid shape entry {}", x)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if shape.len() != 2 {
+            return Err(Error::InvalidFormat(format!(
+                "expected a 2 dimensional array, got shape {:?}",
+                shape
+            )));
+        }
+
+        let row_size = shape[1].checked_mul(T::SIZE).ok_or_else(|| {
+            Error::InvalidFormat(format!("row dimension {} is too large", shape[1]))
+        })?;
+
+        return Ok(Self {
+            reader,
+            n_rows: shape[0],
+            dim: shape[1],
+            row_size,
+            n_read: 0,
+            _t: PhantomData,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        return self.n_rows;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.n_rows == 0;
+    }
+
+    pub fn dim(&self) -> usize {
+        return self.dim;
+    }
+
+    fn read_row(&mut self) -> Result<Vec<T>> {
+        let buf = match read_bytes(&mut self.reader, self.row_size) {
+            Ok(buf) => buf,
+            Err(e) => {
+                // Nothing can follow a truncated row, so iteration ends here.
+                let row = self.n_read;
+                self.n_read = self.n_rows;
+                return Err(Error::InvalidFormat(format!(
+                    "row {} is truncated: {}",
+                    row, e
+                )));
+            }
+        };
+        self.n_read += 1;
+        return Ok(buf.chunks_exact(T::SIZE).map(T::from_le_bytes).collect());
+    }
+}
+
+impl<T: Element> NpyReader<BufReader<File>, T> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        return Self::new(BufReader::new(File::open(path)?));
+    }
+}
+
+impl<R: Read, T: Element> Iterator for NpyReader<R, T> {
+    type Item = Result<Vec<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.n_read >= self.n_rows {
+            return None;
+        }
+        return Some(self.read_row());
+    }
+}
+
+// Extracts the raw value for a key from the python dict literal in a .npy
+// header, e.g. "{'descr': '<f4', 'fortran_order': False, 'shape': (10, 4), }".
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let missing = || Error::InvalidFormat(format!(".npy header is missing '{}'", key));
+
+    let start = header
+        .find(&format!("'{}'", key))
+        .or_else(|| header.find(&format!("\"{}\"", key)))
+        .ok_or_else(missing)?;
+    let rest = &header[start + key.len() + 2..];
+    let rest = rest.trim_start().strip_prefix(':').ok_or_else(missing)?;
+    let rest = rest.trim_start();
+
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|x| x + 1)
+    } else {
+        rest.find(',').or_else(|| rest.find('}'))
+    }
+    .ok_or_else(missing)?;
+
+    return Ok(rest[..end].trim());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn npy(descr: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            descr, shape
+        );
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+
+        let mut buf = b"\x93NUMPY\x01\x00".to_vec();
+        buf.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        buf.extend_from_slice(header.as_bytes());
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn test_vecs_roundtrip() {
+        let vecs = vec![vec![1, 2, 3], vec![], vec![-4, 5]];
+
+        let mut writer = IvecsWriter::new(Vec::new());
+        for vec in &vecs {
+            writer.write(vec).unwrap();
+        }
+        let buf = writer.into_inner().unwrap();
+        assert_eq!(buf.len(), 4 * 3 + 4 * 5);
+
+        let read: Vec<Vec<i32>> = IvecsReader::new(buf.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, vecs);
+
+        let read: Vec<Vec<f32>> = FvecsReader::new(&buf[..16]).collect::<Result<_>>().unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0][0], f32::from_le_bytes(1i32.to_le_bytes()));
+
+        let mut reader = IvecsReader::new(&buf[..buf.len() - 1]);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidFormat(_)))));
+
+        // A partial dimension at the end is an error rather than the end of
+        // the file, and a huge dimension fails without allocating for it.
+        let mut reader = IvecsReader::new(&buf[..buf.len() - 10]);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidFormat(_)))));
+        let huge = i32::MAX.to_le_bytes();
+        assert!(matches!(
+            IvecsReader::new(&huge[..]).next(),
+            Some(Err(Error::InvalidFormat(_)))
+        ));
+    }
+
+    #[test]
+    fn test_bvecs() {
+        let buf = [2, 0, 0, 0, 7, 255, 1, 0, 0, 0, 3];
+        let read: Vec<Vec<u8>> = BvecsReader::new(&buf[..]).collect::<Result<_>>().unwrap();
+        assert_eq!(read, vec![vec![7, 255], vec![3]]);
+    }
+
+    #[test]
+    fn test_npy() {
+        let data: Vec<u8> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let buf = npy("<f4", "(3, 2)", &data);
+
+        let reader = NpyReader::<_, f32>::new(buf.as_slice()).unwrap();
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.dim(), 2);
+        let rows: Vec<Vec<f32>> = reader.collect::<Result<_>>().unwrap();
+        assert_eq!(rows, vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![5.0, 6.0]]);
+
+        assert!(matches!(
+            NpyReader::<_, i32>::new(buf.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            NpyReader::<_, f32>::new(npy("<f4", "(6,)", &data).as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        let rows: Vec<Vec<u8>> = NpyReader::new(npy("|u1", "(2, 3)", &data[..6]).as_slice())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(rows, vec![data[..3].to_vec(), data[3..6].to_vec()]);
+
+        let buf = npy("<f4", "(1, 1000000000000)", &data);
+        let mut reader = NpyReader::<_, f32>::new(buf.as_slice()).unwrap();
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidFormat(_)))));
+
+        // Iteration stops after a truncated row.
+        let buf = npy("<f4", "(3, 2)", &data[..12]);
+        let reader = NpyReader::<_, f32>::new(buf.as_slice()).unwrap();
+        let rows: Vec<Result<Vec<f32>>> = reader.collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].is_ok());
+        assert!(matches!(rows[1], Err(Error::InvalidFormat(_))));
+
+        let mut header = b"\x93NUMPY\x02\x00".to_vec();
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            NpyReader::<_, f32>::new(header.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+}
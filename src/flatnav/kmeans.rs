@@ -0,0 +1,188 @@
+use super::distance::Distance;
+use super::error::{Error, Result};
+use rand::{Rng, SeedableRng};
+
+// Lloyd's k-means with k-means++ seeding. Vectors are assigned with the
+// index's own distance function so that the clusters match how queries are
+// routed, while seeding always weighs by squared euclidean distance since the
+// other distances can be negative. Centroids are averaged in f32 and
+// truncated when DataT is an integer type. Clusters that become empty are
+// restarted from a random vector. Fewer than k centroids are returned when the
+// sample has fewer than k distinct vectors.
+pub fn kmeans<DataT, DistanceFn>(
+    sample: &[Vec<DataT>],
+    k: usize,
+    n_iters: usize,
+    seed: u64,
+    distance_fn: &DistanceFn,
+) -> Result<Vec<Vec<DataT>>>
+where
+    DataT: num_traits::Num + num_traits::NumCast + num_traits::AsPrimitive<f32> + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    if k == 0 {
+        return Err(Error::InvalidArgument(
+            "k-means needs at least one cluster".to_string(),
+        ));
+    }
+    if sample.is_empty() {
+        return Err(Error::EmptyIndex);
+    }
+    let data_dim = sample[0].len();
+    if let Some(vec) = sample.iter().find(|vec| vec.len() != data_dim) {
+        return Err(Error::DimensionMismatch {
+            expected: data_dim,
+            got: vec.len(),
+        });
+    }
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut centroids = seed_centroids(sample, k, &mut rng);
+
+    let k = centroids.len();
+    let mut assignment = vec![usize::MAX; sample.len()];
+    for _ in 0..n_iters {
+        let mut changed = false;
+        for (vec, cluster) in sample.iter().zip(assignment.iter_mut()) {
+            let nearest = nearest(&centroids, vec, distance_fn);
+            if nearest != *cluster {
+                *cluster = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![vec![0.0f32; data_dim]; k];
+        let mut counts = vec![0usize; k];
+        for (vec, &cluster) in sample.iter().zip(assignment.iter()) {
+            counts[cluster] += 1;
+            for (sum, &x) in sums[cluster].iter_mut().zip(vec.iter()) {
+                *sum += x.as_();
+            }
+        }
+
+        for cluster in 0..k {
+            if counts[cluster] == 0 {
+                let i = rng.random_range(0..sample.len());
+                centroids[cluster] = sample[i].clone();
+                continue;
+            }
+            centroids[cluster] = sums[cluster]
+                .iter()
+                .map(|&sum| DataT::from(sum / counts[cluster] as f32).unwrap())
+                .collect();
+        }
+    }
+
+    return Ok(centroids);
+}
+
+fn seed_centroids<DataT>(
+    sample: &[Vec<DataT>],
+    k: usize,
+    rng: &mut rand::rngs::StdRng,
+) -> Vec<Vec<DataT>>
+where
+    DataT: num_traits::AsPrimitive<f32> + Copy,
+{
+    let squared_dist = |a: &[DataT], b: &[DataT]| -> f32 {
+        a.iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| (x.as_() - y.as_()).powi(2))
+            .sum()
+    };
+
+    let first = rng.random_range(0..sample.len());
+    let mut centroids = vec![sample[first].clone()];
+    let mut weights: Vec<f32> = sample
+        .iter()
+        .map(|vec| squared_dist(vec, &sample[first]))
+        .collect();
+    while centroids.len() < k {
+        let total: f32 = weights.iter().sum();
+        // All remaining vectors coincide with a centroid.
+        if total <= 0.0 {
+            break;
+        }
+        let mut target = rng.random_range(0.0..total);
+        let mut next = weights.len() - 1;
+        for (i, &weight) in weights.iter().enumerate() {
+            if target < weight {
+                next = i;
+                break;
+            }
+            target -= weight;
+        }
+        for (vec, weight) in sample.iter().zip(weights.iter_mut()) {
+            *weight = weight.min(squared_dist(vec, &sample[next]));
+        }
+        centroids.push(sample[next].clone());
+    }
+    return centroids;
+}
+
+// Index of the centroid closest to vec.
+pub(super) fn nearest<DataT, DistanceFn>(
+    centroids: &[Vec<DataT>],
+    vec: &[DataT],
+    distance_fn: &DistanceFn,
+) -> usize
+where
+    DistanceFn: Distance<DataT>,
+{
+    let mut best = 0;
+    let mut best_dist = f32::MAX;
+    for (i, centroid) in centroids.iter().enumerate() {
+        let dist = distance_fn.call(vec, centroid);
+        if dist < best_dist {
+            best = i;
+            best_dist = dist;
+        }
+    }
+    return best;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+
+    #[test]
+    fn test_kmeans() {
+        let centers = [[0.0f32, 0.0], [50.0, 0.0], [0.0, 50.0]];
+        let sample: Vec<Vec<f32>> = (0..300)
+            .map(|i| {
+                let center = centers[i % 3];
+                let offset = (i / 3) as f32 / 100.0 - 0.5;
+                vec![center[0] + offset, center[1] - offset]
+            })
+            .collect();
+
+        let centroids = kmeans(&sample, 3, 20, 1, &EuclideanDistance).unwrap();
+        assert_eq!(centroids.len(), 3);
+        for center in centers {
+            let i = nearest(&centroids, &center, &EuclideanDistance);
+            assert!(EuclideanDistance.call(&centroids[i], &center) < 0.1);
+        }
+
+        let sample: Vec<Vec<u8>> = vec![vec![1, 2], vec![3, 4]];
+        assert_eq!(
+            kmeans(&sample, 5, 10, 0, &EuclideanDistance).unwrap().len(),
+            2
+        );
+        assert_eq!(
+            kmeans::<u8, _>(&[], 5, 10, 0, &EuclideanDistance),
+            Err(Error::EmptyIndex)
+        );
+        assert!(matches!(
+            kmeans(&[vec![1u8, 2], vec![3]], 1, 10, 0, &EuclideanDistance),
+            Err(Error::DimensionMismatch { .. })
+        ));
+        assert!(matches!(
+            kmeans(&sample, 0, 10, 0, &EuclideanDistance),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
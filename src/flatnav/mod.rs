@@ -1,11 +1,76 @@
+mod checksum;
+pub mod concurrent;
+mod disk;
 mod distance;
+mod error;
+mod filter;
+mod flat;
+mod graph_stats;
 mod index;
-mod priority_queue;
+pub mod io;
+mod kmeans;
+mod payload;
+mod pq;
+pub mod priority_queue;
 pub mod reordering;
+mod search;
+mod sharded;
 mod storage;
+mod vector_index;
+mod wal;
 
-pub use distance::EuclideanDistance;
+pub use concurrent::ConcurrentIndex;
+
+pub use disk::DiskIndex;
+
+pub use distance::{Distance, EuclideanDistance, InnerProductDistance, SeparableDistance};
+
+pub use error::{Error, Result};
+
+pub use filter::{Attributes, Filter, Value};
+
+pub use flat::FlatIndex;
+
+pub use graph_stats::GraphStats;
+
+pub use index::{Index, NamespaceStats, VerifyReport};
+
+pub use kmeans::kmeans;
 
 pub use reordering::Reordering;
 
+pub use sharded::ShardedIndex;
+
+pub use storage::LabelT;
+
+pub use vector_index::VectorIndex;
+
+pub use wal::DurableIndex;
+
 pub type IndexEuclideanF32 = index::Index<u32, f32, distance::EuclideanDistance>;
+pub type IndexEuclideanU8 = index::Index<u32, u8, distance::EuclideanDistance>;
+pub type IndexEuclideanI8 = index::Index<u32, i8, distance::EuclideanDistance>;
+pub type IndexInnerProductF32 = index::Index<u32, f32, distance::InnerProductDistance>;
+pub type IndexInnerProductU8 = index::Index<u32, u8, distance::InnerProductDistance>;
+pub type IndexInnerProductI8 = index::Index<u32, i8, distance::InnerProductDistance>;
+
+pub type IndexEuclideanF32U16 = index::Index<u16, f32, distance::EuclideanDistance>;
+pub type IndexEuclideanU8U16 = index::Index<u16, u8, distance::EuclideanDistance>;
+pub type IndexEuclideanI8U16 = index::Index<u16, i8, distance::EuclideanDistance>;
+pub type IndexInnerProductF32U16 = index::Index<u16, f32, distance::InnerProductDistance>;
+pub type IndexInnerProductU8U16 = index::Index<u16, u8, distance::InnerProductDistance>;
+pub type IndexInnerProductI8U16 = index::Index<u16, i8, distance::InnerProductDistance>;
+
+pub type IndexEuclideanF32U64 = index::Index<u64, f32, distance::EuclideanDistance>;
+pub type IndexEuclideanU8U64 = index::Index<u64, u8, distance::EuclideanDistance>;
+pub type IndexEuclideanI8U64 = index::Index<u64, i8, distance::EuclideanDistance>;
+pub type IndexInnerProductF32U64 = index::Index<u64, f32, distance::InnerProductDistance>;
+pub type IndexInnerProductU8U64 = index::Index<u64, u8, distance::InnerProductDistance>;
+pub type IndexInnerProductI8U64 = index::Index<u64, i8, distance::InnerProductDistance>;
+
+pub type FlatIndexEuclideanF32 = flat::FlatIndex<f32, distance::EuclideanDistance>;
+pub type FlatIndexEuclideanU8 = flat::FlatIndex<u8, distance::EuclideanDistance>;
+pub type FlatIndexEuclideanI8 = flat::FlatIndex<i8, distance::EuclideanDistance>;
+pub type FlatIndexInnerProductF32 = flat::FlatIndex<f32, distance::InnerProductDistance>;
+pub type FlatIndexInnerProductU8 = flat::FlatIndex<u8, distance::InnerProductDistance>;
+pub type FlatIndexInnerProductI8 = flat::FlatIndex<i8, distance::InnerProductDistance>;
@@ -0,0 +1,128 @@
+use super::error::{Error, Result};
+use super::storage::read_bytes;
+use std::io::{Read, Write};
+
+// Variable length byte payloads stored next to the graph, one per node. The
+// payload of node n is bytes[offsets[n]..offsets[n + 1]], so fixed size
+// payloads cost no more than their size plus one offset per node.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PayloadColumn {
+    offsets: Vec<usize>,
+    bytes: Vec<u8>,
+}
+
+impl PayloadColumn {
+    pub fn new() -> Self {
+        Self {
+            offsets: vec![0],
+            bytes: Vec::new(),
+        }
+    }
+
+    // Column for nodes which were added before any payload was set.
+    pub fn with_empty(n_nodes: usize) -> Self {
+        Self {
+            offsets: vec![0; n_nodes + 1],
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, payload: &[u8]) {
+        self.bytes.extend_from_slice(payload);
+        self.offsets.push(self.bytes.len());
+    }
+
+    pub fn get(&self, node: usize) -> &[u8] {
+        return &self.bytes[self.offsets[node]..self.offsets[node + 1]];
+    }
+
+    // perm[n] = i means that node n is moved to pos i
+    pub fn reorder(&mut self, perm: &[usize]) {
+        let mut order = vec![0; perm.len()];
+        for (node, &pos) in perm.iter().enumerate() {
+            order[pos] = node;
+        }
+
+        let mut reordered = Self::new();
+        reordered.bytes.reserve(self.bytes.len());
+        for node in order {
+            reordered.push(self.get(node));
+        }
+        *self = reordered;
+    }
+
+    pub fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&(self.bytes.len() as u64).to_le_bytes())?;
+        for &offset in &self.offsets[1..] {
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+        }
+        writer.write_all(&self.bytes)?;
+        return Ok(());
+    }
+
+    pub fn load<R: Read>(reader: &mut R, n_nodes: usize) -> Result<Self> {
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf)?;
+        let n_bytes = u64::from_le_bytes(buf) as usize;
+
+        let mut offsets = Vec::with_capacity(n_nodes + 1);
+        offsets.push(0);
+        for _ in 0..n_nodes {
+            reader.read_exact(&mut buf)?;
+            let offset = u64::from_le_bytes(buf) as usize;
+            if offset < *offsets.last().unwrap() || offset > n_bytes {
+                return Err(Error::InvalidFormat(format!(
+                    "invalid payload offset {}",
+                    offset
+                )));
+            }
+            offsets.push(offset);
+        }
+        if *offsets.last().unwrap() != n_bytes {
+            return Err(Error::InvalidFormat(
+                "payload offsets do not cover payload bytes".to_string(),
+            ));
+        }
+
+        let bytes = read_bytes(reader, n_bytes)?;
+
+        return Ok(Self { offsets, bytes });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_column() {
+        let mut column = PayloadColumn::with_empty(1);
+        column.push(b"abc");
+        column.push(b"");
+        column.push(b"de");
+
+        assert_eq!(column.get(0), b"");
+        assert_eq!(column.get(1), b"abc");
+        assert_eq!(column.get(3), b"de");
+
+        column.reorder(&[3, 0, 2, 1]);
+        assert_eq!(column.get(0), b"abc");
+        assert_eq!(column.get(1), b"de");
+        assert_eq!(column.get(2), b"");
+        assert_eq!(column.get(3), b"");
+
+        let mut buf = Vec::new();
+        column.save(&mut buf).unwrap();
+        assert_eq!(PayloadColumn::load(&mut buf.as_slice(), 4).unwrap(), column);
+        assert!(PayloadColumn::load(&mut &buf[..buf.len() - 1], 4).is_err());
+
+        // A corrupted length fails at the end of the data.
+        let huge = (1u64 << 40).to_le_bytes();
+        buf[..8].copy_from_slice(&huge);
+        buf[32..40].copy_from_slice(&huge);
+        assert!(matches!(
+            PayloadColumn::load(&mut buf.as_slice(), 4),
+            Err(Error::Io(_))
+        ));
+    }
+}
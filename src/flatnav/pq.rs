@@ -0,0 +1,215 @@
+use super::distance::{EuclideanDistance, SeparableDistance};
+use super::error::{Error, Result};
+use super::kmeans::{kmeans, nearest};
+use super::storage::{read_bytes, read_u64};
+use std::io::{Read, Write};
+
+const MAX_CENTROIDS: usize = 256;
+const KMEANS_ITERS: usize = 15;
+
+// Product quantizer which splits vectors into contiguous subspaces and encodes
+// each subspace as the byte index of the nearest of up to 256 centroids, so a
+// vector costs one byte per subspace. Codebooks are trained and compared in f32
+// regardless of the data type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProductQuantizer {
+    // Subspace i covers dimensions bounds[i]..bounds[i + 1].
+    bounds: Vec<usize>,
+    codebooks: Vec<Vec<Vec<f32>>>,
+}
+
+impl ProductQuantizer {
+    pub fn train(sample: &[Vec<f32>], n_subspaces: usize) -> Result<Self> {
+        if sample.is_empty() {
+            return Err(Error::EmptyIndex);
+        }
+        let data_dim = sample[0].len();
+        let n_subspaces = n_subspaces.clamp(1, data_dim.max(1));
+        let bounds: Vec<usize> = (0..=n_subspaces)
+            .map(|i| i * data_dim / n_subspaces)
+            .collect();
+
+        let mut codebooks = Vec::with_capacity(n_subspaces);
+        for (i, range) in bounds.windows(2).enumerate() {
+            let sub_sample: Vec<Vec<f32>> = sample
+                .iter()
+                .map(|vec| vec[range[0]..range[1]].to_vec())
+                .collect();
+            codebooks.push(kmeans(
+                &sub_sample,
+                MAX_CENTROIDS,
+                KMEANS_ITERS,
+                i as u64,
+                &EuclideanDistance,
+            )?);
+        }
+
+        return Ok(Self { bounds, codebooks });
+    }
+
+    pub fn data_dim(&self) -> usize {
+        return *self.bounds.last().unwrap();
+    }
+
+    pub fn n_subspaces(&self) -> usize {
+        return self.codebooks.len();
+    }
+
+    pub fn encode(&self, vec: &[f32], out: &mut Vec<u8>) {
+        for (range, codebook) in self.bounds.windows(2).zip(self.codebooks.iter()) {
+            out.push(nearest(codebook, &vec[range[0]..range[1]], &EuclideanDistance) as u8);
+        }
+    }
+
+    // Whether every byte of the code is a centroid of its subspace.
+    pub fn is_valid(&self, code: &[u8]) -> bool {
+        return code.len() == self.codebooks.len()
+            && code
+                .iter()
+                .zip(self.codebooks.iter())
+                .all(|(&c, codebook)| (c as usize) < codebook.len());
+    }
+
+    // Partial distances from the query to every centroid, MAX_CENTROIDS per
+    // subspace, for comparing many codes with one query through
+    // table_distance.
+    pub fn distance_table<D: SeparableDistance>(&self, query: &[f32], distance_fn: &D) -> Vec<f32> {
+        let mut table = vec![0.0; self.codebooks.len() * MAX_CENTROIDS];
+        for ((range, codebook), row) in self
+            .bounds
+            .windows(2)
+            .zip(self.codebooks.iter())
+            .zip(table.chunks_mut(MAX_CENTROIDS))
+        {
+            for (centroid, dist) in codebook.iter().zip(row.iter_mut()) {
+                *dist = distance_fn.partial(&query[range[0]..range[1]], centroid);
+            }
+        }
+        return table;
+    }
+
+    pub fn table_distance<D: SeparableDistance>(
+        &self,
+        table: &[f32],
+        code: &[u8],
+        distance_fn: &D,
+    ) -> f32 {
+        let sum = code
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| table[i * MAX_CENTROIDS + c as usize])
+            .sum();
+        return distance_fn.finish(sum);
+    }
+
+    // [data_dim][n_subspaces][for each subspace: n_centroids, then the
+    // centroids as f32]
+    pub fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&(self.data_dim() as u64).to_le_bytes())?;
+        writer.write_all(&(self.codebooks.len() as u64).to_le_bytes())?;
+        for codebook in &self.codebooks {
+            writer.write_all(&(codebook.len() as u64).to_le_bytes())?;
+            for centroid in codebook {
+                for x in centroid {
+                    writer.write_all(&x.to_le_bytes())?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // Nothing is allocated up front from the sizes in the header, so that a
+    // corrupted header fails at the end of the input instead.
+    pub fn load<R: Read>(reader: &mut R) -> Result<Self> {
+        let data_dim = read_u64(reader)? as usize;
+        let n_subspaces = read_u64(reader)? as usize;
+        if data_dim == 0 || n_subspaces == 0 || n_subspaces > data_dim {
+            return Err(Error::InvalidFormat(format!(
+                "{} subspaces for data dim {}",
+                n_subspaces, data_dim
+            )));
+        }
+
+        let mut bounds = vec![0];
+        let mut codebooks = Vec::new();
+        for i in 1..=n_subspaces {
+            let end = (i as u128 * data_dim as u128 / n_subspaces as u128) as usize;
+            let sub_dim = end - bounds[i - 1];
+            let n_centroids = read_u64(reader)? as usize;
+            if n_centroids == 0 || n_centroids > MAX_CENTROIDS {
+                return Err(Error::InvalidFormat(format!(
+                    "invalid codebook size {}",
+                    n_centroids
+                )));
+            }
+            let mut codebook = Vec::with_capacity(n_centroids);
+            for _ in 0..n_centroids {
+                let centroid = read_bytes(reader, sub_dim.saturating_mul(4))?;
+                codebook.push(
+                    centroid
+                        .chunks_exact(4)
+                        .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+                        .collect(),
+                );
+            }
+            codebooks.push(codebook);
+            bounds.push(end);
+        }
+
+        return Ok(Self { bounds, codebooks });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::Distance;
+
+    #[test]
+    fn test_product_quantizer() {
+        let sample: Vec<Vec<f32>> = (0..500)
+            .map(|i| (0..10).map(|j| ((i * 7 + j * 3) % 50) as f32).collect())
+            .collect();
+        let pq = ProductQuantizer::train(&sample, 4).unwrap();
+        assert_eq!(pq.n_subspaces(), 4);
+
+        let mut code = Vec::new();
+        for vec in &sample {
+            code.clear();
+            pq.encode(vec, &mut code);
+            assert_eq!(code.len(), 4);
+            assert!(pq.is_valid(&code));
+            let table = pq.distance_table(vec, &EuclideanDistance);
+            assert!(pq.table_distance(&table, &code, &EuclideanDistance) < 1e-3);
+        }
+        assert!(!pq.is_valid(&[0, 0, 0]));
+
+        // The table distance matches the distance to the reconstructed vector.
+        let table = pq.distance_table(&sample[1], &EuclideanDistance);
+        code.clear();
+        pq.encode(&sample[0], &mut code);
+        let decoded: Vec<f32> = code
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &c)| pq.codebooks[i][c as usize].clone())
+            .collect();
+        let dist = EuclideanDistance.call(&sample[1], &decoded);
+        assert!((pq.table_distance(&table, &code, &EuclideanDistance) - dist).abs() < 1e-3);
+
+        let mut buf = Vec::new();
+        pq.save(&mut buf).unwrap();
+        assert_eq!(ProductQuantizer::load(&mut buf.as_slice()).unwrap(), pq);
+
+        // Corrupted sizes fail without allocating for them.
+        for (data_dim, n_subspaces) in [(0u64, 1u64), (1 << 60, 1 << 60), (1 << 60, 1)] {
+            let mut header = data_dim.to_le_bytes().to_vec();
+            header.extend_from_slice(&n_subspaces.to_le_bytes());
+            header.extend_from_slice(&1u64.to_le_bytes());
+            assert!(ProductQuantizer::load(&mut header.as_slice()).is_err());
+        }
+        assert_eq!(
+            ProductQuantizer::train(&sample, 100).unwrap().n_subspaces(),
+            10
+        );
+    }
+}
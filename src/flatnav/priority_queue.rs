@@ -19,15 +19,15 @@ impl<T> Eq for Closest<T> {}
 
 impl<T> PartialOrd for Closest<T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        return other.dist.partial_cmp(&self.dist);
+        return Some(self.cmp(other));
     }
 }
 
 impl<T> Ord for Closest<T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        return self
+        return other
             .dist
-            .partial_cmp(&other.dist)
+            .partial_cmp(&self.dist)
             .unwrap_or(Ordering::Equal);
     }
 }
@@ -47,15 +47,15 @@ impl<T> Eq for Furthest<T> {}
 
 impl<T> PartialOrd for Furthest<T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        return self.dist.partial_cmp(&other.dist);
+        return Some(self.cmp(other));
     }
 }
 
 impl<T> Ord for Furthest<T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        return other
+        return self
             .dist
-            .partial_cmp(&self.dist)
+            .partial_cmp(&other.dist)
             .unwrap_or(Ordering::Equal);
     }
 }
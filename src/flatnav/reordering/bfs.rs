@@ -0,0 +1,64 @@
+use super::{check_out_nodes, invert, undirected, Reordering};
+use crate::flatnav::error::Result;
+use std::collections::VecDeque;
+
+pub struct BfsOrder {
+    root: usize,
+}
+
+impl BfsOrder {
+    pub fn new(root: usize) -> Self {
+        Self { root }
+    }
+}
+
+impl Reordering for BfsOrder {
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+        check_out_nodes(out_nodes)?;
+        let adj = undirected(out_nodes);
+
+        let mut order = Vec::with_capacity(out_nodes.len());
+        let mut visited = vec![false; out_nodes.len()];
+        let mut queue = VecDeque::new();
+
+        // Start from the requested root, then pick up any components that are
+        // not reachable from it in id order.
+        let roots = (self.root..out_nodes.len()).chain(0..self.root.min(out_nodes.len()));
+
+        for root in roots {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            queue.push_back(root);
+
+            while let Some(node) = queue.pop_front() {
+                order.push(node);
+                for &nbr in &adj[node] {
+                    if !visited[nbr] {
+                        visited[nbr] = true;
+                        queue.push_back(nbr);
+                    }
+                }
+            }
+        }
+
+        return Ok(invert(&order));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bfs_order() {
+        let out_nodes = vec![vec![3], vec![4], vec![0], vec![], vec![], vec![1]];
+
+        let perm = BfsOrder::new(0).reorder(&out_nodes).unwrap();
+        assert_eq!(invert(&perm), vec![0, 2, 3, 1, 4, 5]);
+
+        let perm = BfsOrder::new(4).reorder(&out_nodes).unwrap();
+        assert_eq!(invert(&perm), vec![4, 1, 5, 0, 2, 3]);
+    }
+}
@@ -0,0 +1,93 @@
+use super::{check_out_nodes, in_nodes, invert, Reordering};
+use crate::flatnav::error::Result;
+
+fn in_degrees(out_nodes: &[Vec<usize>]) -> Vec<usize> {
+    return in_nodes(out_nodes).iter().map(|x| x.len()).collect();
+}
+
+// Orders nodes by descending in-degree so that hubs, which are visited by most
+// searches, are packed together at the front of storage.
+pub struct DegreeSort;
+
+impl DegreeSort {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for DegreeSort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reordering for DegreeSort {
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+        check_out_nodes(out_nodes)?;
+        let degrees = in_degrees(out_nodes);
+
+        let mut order: Vec<usize> = (0..out_nodes.len()).collect();
+        order.sort_by_key(|&node| std::cmp::Reverse(degrees[node]));
+
+        return Ok(invert(&order));
+    }
+}
+
+// Moves nodes with above average in-degree to the front while keeping the
+// relative order of both hubs and non-hubs, which preserves whatever locality
+// the insertion order already had.
+pub struct HubCluster;
+
+impl HubCluster {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for HubCluster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reordering for HubCluster {
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+        check_out_nodes(out_nodes)?;
+        let degrees = in_degrees(out_nodes);
+
+        let n_edges: usize = degrees.iter().sum();
+        let is_hub = |node: usize| degrees[node] * out_nodes.len() > n_edges;
+
+        let (mut order, rest): (Vec<usize>, Vec<usize>) =
+            (0..out_nodes.len()).partition(|&node| is_hub(node));
+        order.extend(rest);
+
+        return Ok(invert(&order));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degree_sort() {
+        let out_nodes = vec![vec![3], vec![3, 2], vec![3], vec![2], vec![0, 2]];
+
+        let perm = DegreeSort::new().reorder(&out_nodes).unwrap();
+        assert_eq!(invert(&perm), vec![2, 3, 0, 1, 4]);
+    }
+
+    #[test]
+    fn test_hub_cluster() {
+        let out_nodes = vec![vec![3], vec![3, 2], vec![3], vec![2], vec![0, 2]];
+
+        let perm = HubCluster::new().reorder(&out_nodes).unwrap();
+        assert_eq!(invert(&perm), vec![2, 3, 0, 1, 4]);
+
+        let out_nodes = vec![vec![4], vec![0], vec![4], vec![4], vec![1]];
+
+        let perm = HubCluster::new().reorder(&out_nodes).unwrap();
+        assert_eq!(invert(&perm), vec![4, 0, 1, 2, 3]);
+    }
+}
@@ -1,71 +1,114 @@
-use super::Reordering;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use super::{check_out_nodes, in_nodes, invert, Reordering};
+use crate::flatnav::error::{Error, Result};
 
+const NONE: usize = usize::MAX;
+
+trait Queue {
+    fn update(&mut self, node: usize, delta: i32) -> Result<()>;
+
+    fn pop(&mut self) -> Option<usize>;
+}
+
+// Max priority queue over node scores that only change by small increments,
+// following the unit heap from the GOrder paper. Nodes with the same score are
+// kept in an intrusive doubly linked list per score so that every update is
+// O(|delta|) with no hashing or allocation, and pop only has to walk down from
+// the highest non empty bucket.
 struct PriorityQueue {
-    node_to_score: HashMap<usize, i32>,
-    scores: BTreeMap<i32, HashSet<usize>>,
+    scores: Vec<usize>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+    heads: Vec<usize>,
+    top: usize,
+    len: usize,
 }
 
 impl PriorityQueue {
     fn new(n_nodes: usize) -> Self {
-        let mut scores = BTreeMap::new();
-        scores.insert(0, (0..n_nodes).collect());
-        Self {
-            node_to_score: (0..n_nodes).map(|x| (x, 0)).collect(),
-            scores: scores,
+        let mut queue = Self {
+            scores: vec![0; n_nodes],
+            prev: vec![NONE; n_nodes],
+            next: vec![NONE; n_nodes],
+            heads: vec![NONE],
+            top: 0,
+            len: n_nodes,
+        };
+        for node in (0..n_nodes).rev() {
+            queue.link(node, 0);
         }
+        return queue;
     }
 
-    fn update(&mut self, node: usize, delta: i32) {
-        let score = self.node_to_score.get(&node);
-        if score.is_none() {
-            return;
+    fn link(&mut self, node: usize, score: usize) {
+        if score >= self.heads.len() {
+            self.heads.resize(score + 1, NONE);
         }
 
-        let score = *score.unwrap();
-        let new_score = score + delta;
-        self.node_to_score.insert(node, new_score);
+        let head = self.heads[score];
+        self.prev[node] = NONE;
+        self.next[node] = head;
+        if head != NONE {
+            self.prev[head] = node;
+        }
+        self.heads[score] = node;
+        self.scores[node] = score;
+    }
 
-        if let Some(nodes) = self.scores.get_mut(&score) {
-            if nodes.len() == 1 {
-                self.scores.remove(&score);
-            } else {
-                nodes.remove(&node);
-            }
+    fn unlink(&mut self, node: usize) {
+        let (prev, next) = (self.prev[node], self.next[node]);
+        if prev != NONE {
+            self.next[prev] = next;
         } else {
-            panic!("scores does not contain entry for old score")
+            self.heads[self.scores[node]] = next;
         }
-
-        if !self.scores.contains_key(&new_score) {
-            self.scores.insert(new_score, HashSet::new());
+        if next != NONE {
+            self.prev[next] = prev;
         }
+        self.prev[node] = NONE;
+        self.next[node] = NONE;
+    }
 
-        if let Some(nodes) = self.scores.get_mut(&new_score) {
-            nodes.insert(node);
-        } else {
-            panic!("scores does not contain entry for new score")
-        }
+    fn contains(&self, node: usize) -> bool {
+        return self.scores[node] != NONE;
     }
+}
 
-    fn pop(&mut self) -> usize {
-        assert!(
-            self.scores.len() > 0,
-            "queue must not be empty when calling pop"
-        );
+impl Queue for PriorityQueue {
+    fn update(&mut self, node: usize, delta: i32) -> Result<()> {
+        if !self.contains(node) {
+            return Ok(());
+        }
 
-        let mut last = self.scores.last_entry().unwrap();
+        let score = self.scores[node];
+        let new_score = score.checked_add_signed(delta as isize).ok_or_else(|| {
+            Error::InvalidGraph(format!(
+                "gorder score of node {} would become negative",
+                node
+            ))
+        })?;
 
-        let node = *last.get().iter().next().unwrap();
+        self.unlink(node);
+        self.link(node, new_score);
+        self.top = self.top.max(new_score);
 
-        if last.get().len() == 1 {
-            last.remove();
-        } else {
-            last.get_mut().remove(&node);
+        return Ok(());
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
         }
 
-        self.node_to_score.remove(&node);
+        while self.heads[self.top] == NONE {
+            self.top -= 1;
+        }
+
+        let node = self.heads[self.top];
+        self.unlink(node);
+        self.scores[node] = NONE;
+        self.len -= 1;
 
-        return node;
+        return Some(node);
     }
 }
 
@@ -75,37 +118,40 @@ pub struct GOrder {
 
 impl GOrder {
     pub fn new(w: usize) -> Self {
-        Self { w: w }
+        Self { w }
     }
 }
 
-impl Reordering for GOrder {
-    fn reorder(&self, out_nodes: &Vec<Vec<usize>>) -> Vec<usize> {
-        let mut in_nodes = Vec::with_capacity(out_nodes.len());
-        in_nodes.resize(out_nodes.len(), Vec::new());
-        for (node, nbrs) in out_nodes.iter().enumerate() {
-            for nbr in nbrs {
-                in_nodes[*nbr].push(node);
-            }
-        }
+impl GOrder {
+    fn order<Q: Queue>(&self, out_nodes: &[Vec<usize>], mut queue: Q) -> Result<Vec<usize>> {
+        check_out_nodes(out_nodes)?;
+        let in_nodes = in_nodes(out_nodes);
 
         let mut perm = Vec::with_capacity(out_nodes.len());
-        let mut queue = PriorityQueue::new(out_nodes.len());
-        queue.update(0, 1);
+        if out_nodes.is_empty() {
+            return Ok(perm);
+        }
+        queue.update(0, 1)?;
 
         for i in 0..out_nodes.len() {
-            let next = queue.pop();
+            let next = queue.pop().ok_or_else(|| {
+                Error::InvalidGraph(format!(
+                    "gorder queue exhausted after {} of {} nodes",
+                    i,
+                    out_nodes.len()
+                ))
+            })?;
             perm.push(next);
 
             for &u in &out_nodes[next] {
-                queue.update(u, 1);
+                queue.update(u, 1)?;
             }
 
             for &u in &in_nodes[next] {
-                queue.update(u, 1);
+                queue.update(u, 1)?;
 
                 for &v in &out_nodes[u] {
-                    queue.update(v, 1);
+                    queue.update(v, 1)?;
                 }
             }
 
@@ -113,68 +159,147 @@ impl Reordering for GOrder {
                 let last = perm[i - self.w];
 
                 for &u in &out_nodes[last] {
-                    queue.update(u, -1);
+                    queue.update(u, -1)?;
                 }
 
                 for &u in &in_nodes[last] {
-                    queue.update(u, -1);
+                    queue.update(u, -1)?;
 
                     for &v in &out_nodes[u] {
-                        queue.update(v, -1);
+                        queue.update(v, -1)?;
                     }
                 }
             }
         }
 
+        return Ok(perm);
+    }
+}
+
+impl Reordering for GOrder {
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+        let perm = self.order(out_nodes, PriorityQueue::new(out_nodes.len()))?;
+
         // perm[i] = n means that node n goes to position i. Inverting it means
         // that perm_inv[n] = i means that node n goes to position i which allows
         // for easy lookup for mapping nodes
-        let mut perm_inv = vec![0; out_nodes.len()];
-        for (i, &node) in perm.iter().enumerate() {
-            perm_inv[node] = i;
-        }
-
-        return perm_inv;
+        return Ok(invert(&perm));
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
+    use rand::{Rng, SeedableRng};
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::time::Instant;
 
     impl PriorityQueue {
         fn nodes(&self) -> Vec<usize> {
-            self.scores.iter().fold(Vec::new(), |mut vec, (_, nodes)| {
-                vec.extend(nodes.iter());
-                vec
-            })
+            let mut nodes = Vec::new();
+            for &head in &self.heads {
+                let mut node = head;
+                while node != NONE {
+                    nodes.push(node);
+                    node = self.next[node];
+                }
+            }
+            nodes
         }
     }
 
+    // The original hash map based queue, kept as a reference for the benchmark.
+    struct HashPriorityQueue {
+        node_to_score: HashMap<usize, i32>,
+        scores: BTreeMap<i32, HashSet<usize>>,
+    }
+
+    impl HashPriorityQueue {
+        fn new(n_nodes: usize) -> Self {
+            let mut scores = BTreeMap::new();
+            scores.insert(0, (0..n_nodes).collect());
+            Self {
+                node_to_score: (0..n_nodes).map(|x| (x, 0)).collect(),
+                scores,
+            }
+        }
+    }
+
+    impl Queue for HashPriorityQueue {
+        fn update(&mut self, node: usize, delta: i32) -> Result<()> {
+            let score = match self.node_to_score.get(&node) {
+                Some(&score) => score,
+                None => return Ok(()),
+            };
+
+            let new_score = score + delta;
+            self.node_to_score.insert(node, new_score);
+
+            let nodes = self.scores.get_mut(&score).unwrap();
+            if nodes.len() == 1 {
+                self.scores.remove(&score);
+            } else {
+                nodes.remove(&node);
+            }
+            self.scores.entry(new_score).or_default().insert(node);
+
+            Ok(())
+        }
+
+        fn pop(&mut self) -> Option<usize> {
+            let mut last = self.scores.last_entry()?;
+            let node = *last.get().iter().next()?;
+            if last.get().len() == 1 {
+                last.remove();
+            } else {
+                last.get_mut().remove(&node);
+            }
+            self.node_to_score.remove(&node);
+            Some(node)
+        }
+    }
+
+    fn random_graph(n_nodes: usize, degree: usize, seed: u64) -> Vec<Vec<usize>> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        (0..n_nodes)
+            .map(|node| {
+                // Mostly local edges with a few long range ones, like a built index.
+                (0..degree)
+                    .map(|i| {
+                        if i % 4 == 0 {
+                            rng.random_range(0..n_nodes)
+                        } else {
+                            (node + rng.random_range(1..64)) % n_nodes
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
     #[test]
     fn test_priority_queue_basic() {
         let mut queue = PriorityQueue::new(5);
-        (0..5).for_each(|i| queue.update(i, i as i32));
+        (0..5).for_each(|i| queue.update(i, i as i32).unwrap());
 
         assert_eq!(queue.nodes(), vec![0, 1, 2, 3, 4]);
 
-        queue.update(2, 4);
+        queue.update(2, 4).unwrap();
         assert_eq!(queue.nodes(), vec![0, 1, 3, 4, 2]);
 
-        queue.update(3, 2);
+        queue.update(3, 2).unwrap();
         assert_eq!(queue.nodes(), vec![0, 1, 4, 3, 2]);
 
-        queue.update(0, 3);
+        queue.update(0, 3).unwrap();
         assert_eq!(queue.nodes(), vec![1, 0, 4, 3, 2]);
 
-        queue.update(0, -3);
+        queue.update(0, -3).unwrap();
         assert_eq!(queue.nodes(), vec![0, 1, 4, 3, 2]);
 
-        assert_eq!(queue.pop(), 2);
+        assert_eq!(queue.pop(), Some(2));
         assert_eq!(queue.nodes(), vec![0, 1, 4, 3]);
 
-        assert_eq!(queue.pop(), 3);
+        assert_eq!(queue.pop(), Some(3));
         assert_eq!(queue.nodes(), vec![0, 1, 4]);
     }
 
@@ -185,7 +310,7 @@ mod tests {
         for i in 0..10 {
             for n in 0..100 {
                 if (n % 10) >= i {
-                    queue.update(n, 1);
+                    queue.update(n, 1).unwrap();
                 }
             }
         }
@@ -193,12 +318,13 @@ mod tests {
         let mut popped = HashSet::new();
         for i in (0..10).rev() {
             for _ in 0..10 {
-                let next = queue.pop();
+                let next = queue.pop().unwrap();
                 assert_eq!(next % 10, i);
                 popped.insert(next);
             }
         }
 
+        assert_eq!(queue.pop(), None);
         assert_eq!(popped.len(), 100);
 
         for i in 0..100 {
@@ -212,8 +338,87 @@ mod tests {
 
         let gorder = GOrder::new(2);
 
-        let perm = gorder.reorder(&out_nodes);
+        let perm = gorder.reorder(&out_nodes).unwrap();
 
         assert_eq!(perm, vec![0, 1, 2, 3, 4]);
     }
+
+    #[test]
+    fn test_priority_queue_matches_hash_queue() {
+        const N_NODES: usize = 500;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        let mut queue = PriorityQueue::new(N_NODES);
+        let mut expected = HashPriorityQueue::new(N_NODES);
+        let mut increments: Vec<usize> = Vec::new();
+
+        while !expected.node_to_score.is_empty() {
+            for _ in 0..rng.random_range(0..20) {
+                if !increments.is_empty() && rng.random_bool(0.4) {
+                    let node = increments.swap_remove(rng.random_range(0..increments.len()));
+                    queue.update(node, -1).unwrap();
+                    expected.update(node, -1).unwrap();
+                } else {
+                    let node = rng.random_range(0..N_NODES);
+                    increments.push(node);
+                    queue.update(node, 1).unwrap();
+                    expected.update(node, 1).unwrap();
+                }
+            }
+
+            // Ties may be broken differently, but the popped node must always
+            // have the maximum score.
+            let node = queue.pop().unwrap();
+            let max_score = *expected.scores.last_key_value().unwrap().0;
+            assert_eq!(expected.node_to_score[&node], max_score);
+
+            let score = expected.node_to_score.remove(&node).unwrap();
+            let nodes = expected.scores.get_mut(&score).unwrap();
+            nodes.remove(&node);
+            if nodes.is_empty() {
+                expected.scores.remove(&score);
+            }
+        }
+
+        assert_eq!(queue.pop(), None);
+    }
+
+    // cargo test --release bench_gorder -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_gorder() {
+        let out_nodes = random_graph(1_000_000, 16, 2);
+        let gorder = GOrder::new(5);
+
+        let start = Instant::now();
+        gorder
+            .order(&out_nodes, PriorityQueue::new(out_nodes.len()))
+            .unwrap();
+        let bucketed = start.elapsed();
+
+        let start = Instant::now();
+        gorder
+            .order(&out_nodes, HashPriorityQueue::new(out_nodes.len()))
+            .unwrap();
+        let hashed = start.elapsed();
+
+        println!(
+            "gorder 1M nodes: bucketed {:.2?} hashed {:.2?} speedup {:.1}x",
+            bucketed,
+            hashed,
+            hashed.as_secs_f64() / bucketed.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn test_gorder_invalid_graph() {
+        let out_nodes = vec![vec![1, 5], vec![0]];
+
+        let gorder = GOrder::new(2);
+
+        assert!(matches!(
+            gorder.reorder(&out_nodes),
+            Err(Error::InvalidGraph(_))
+        ));
+    }
 }
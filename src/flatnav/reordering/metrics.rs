@@ -0,0 +1,133 @@
+use super::{check_out_nodes, in_nodes, validate_permutation};
+use crate::flatnav::error::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    // Mean of |perm[u] - perm[v]| over all edges u -> v.
+    pub avg_gap: f64,
+    // Mean of log2(|perm[u] - perm[v]| + 1) over all edges, which weighs small
+    // gaps more heavily and tracks how well neighbors share cache lines/pages.
+    pub avg_log_gap: f64,
+    // Largest |perm[u] - perm[v]| over all edges.
+    pub bandwidth: usize,
+    // The GOrder objective: sum of S(u, v) over all pairs placed within w of
+    // each other, where S counts shared in-neighbors plus edges between them.
+    pub gorder_score: u64,
+}
+
+fn shared_count(a: &[usize], b: &[usize]) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                count += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    return count;
+}
+
+// perm[n] = i means that node n is placed at position i, as returned by
+// Reordering::reorder. Pass the identity to measure the current layout.
+pub fn compute(out_nodes: &[Vec<usize>], perm: &[usize], w: usize) -> Result<Metrics> {
+    check_out_nodes(out_nodes)?;
+    validate_permutation(perm, out_nodes.len())?;
+
+    let mut n_edges = 0;
+    let mut gap_sum = 0.0;
+    let mut log_gap_sum = 0.0;
+    let mut bandwidth = 0;
+
+    for (u, nbrs) in out_nodes.iter().enumerate() {
+        for &v in nbrs {
+            let gap = perm[u].abs_diff(perm[v]);
+            n_edges += 1;
+            gap_sum += gap as f64;
+            log_gap_sum += (gap as f64 + 1.0).log2();
+            bandwidth = bandwidth.max(gap);
+        }
+    }
+
+    let mut in_nodes = in_nodes(out_nodes);
+    in_nodes.iter_mut().for_each(|x| x.sort_unstable());
+
+    let mut order = vec![0; out_nodes.len()];
+    for (node, &pos) in perm.iter().enumerate() {
+        order[pos] = node;
+    }
+
+    let mut gorder_score = 0;
+    for (pos, &v) in order.iter().enumerate() {
+        for &u in &order[pos.saturating_sub(w)..pos] {
+            let edges = out_nodes[u].iter().filter(|&&x| x == v).count()
+                + out_nodes[v].iter().filter(|&&x| x == u).count();
+            gorder_score += (shared_count(&in_nodes[u], &in_nodes[v]) + edges) as u64;
+        }
+    }
+
+    let mean = |sum: f64| {
+        if n_edges == 0 {
+            0.0
+        } else {
+            sum / n_edges as f64
+        }
+    };
+
+    return Ok(Metrics {
+        avg_gap: mean(gap_sum),
+        avg_log_gap: mean(log_gap_sum),
+        bandwidth,
+        gorder_score,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::error::Error;
+    use crate::flatnav::reordering::{GOrder, Reordering};
+
+    #[test]
+    fn test_metrics() {
+        // 0 -> 2, 1 -> 2, 2 -> 0
+        let out_nodes = vec![vec![2], vec![2], vec![0]];
+
+        let metrics = compute(&out_nodes, &[0, 1, 2], 1).unwrap();
+        assert_eq!(metrics.avg_gap, 5.0 / 3.0);
+        assert_eq!(metrics.avg_log_gap, (2.0 * 3f64.log2() + 1.0) / 3.0);
+        assert_eq!(metrics.bandwidth, 2);
+        // Only (1, 2) is adjacent in the window, with the edge 1 -> 2.
+        assert_eq!(metrics.gorder_score, 1);
+
+        let metrics = compute(&out_nodes, &[0, 2, 1], 1).unwrap();
+        assert_eq!(metrics.avg_gap, 1.0);
+        assert_eq!(metrics.avg_log_gap, 1.0);
+        assert_eq!(metrics.bandwidth, 1);
+        // (0, 2) has two edges and (2, 1) one edge.
+        assert_eq!(metrics.gorder_score, 3);
+
+        assert!(matches!(
+            compute(&out_nodes, &[0, 0, 1], 1),
+            Err(Error::InvalidPermutation(_))
+        ));
+    }
+
+    #[test]
+    fn test_metrics_gorder_improves_score() {
+        let n_nodes = 200;
+        let out_nodes: Vec<Vec<usize>> = (0..n_nodes)
+            .map(|node| vec![(node * 37 + 1) % n_nodes, (node * 37 + 2) % n_nodes])
+            .collect();
+        let identity: Vec<usize> = (0..n_nodes).collect();
+
+        let before = compute(&out_nodes, &identity, 5).unwrap();
+        let perm = GOrder::new(5).reorder(&out_nodes).unwrap();
+        let after = compute(&out_nodes, &perm, 5).unwrap();
+
+        assert!(after.gorder_score > before.gorder_score);
+    }
+}
@@ -1,7 +1,124 @@
+mod bfs;
+mod degree;
 mod gorder;
+pub mod metrics;
+mod random;
+mod rcm;
 
+use super::error::{Error, Result};
+
+pub use bfs::BfsOrder;
+pub use degree::{DegreeSort, HubCluster};
 pub use gorder::GOrder;
+pub use random::RandomOrder;
+pub use rcm::ReverseCuthillMckee;
 
 pub trait Reordering {
-    fn reorder(&self, out_nodes: &Vec<Vec<usize>>) -> Vec<usize>;
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>>;
+}
+
+fn check_out_nodes(out_nodes: &[Vec<usize>]) -> Result<()> {
+    for (node, nbrs) in out_nodes.iter().enumerate() {
+        for &nbr in nbrs {
+            if nbr >= out_nodes.len() {
+                return Err(Error::InvalidGraph(format!(
+                    "node {} has out of range neighbor {}",
+                    node, nbr
+                )));
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn in_nodes(out_nodes: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut in_nodes = vec![Vec::new(); out_nodes.len()];
+    for (node, nbrs) in out_nodes.iter().enumerate() {
+        for &nbr in nbrs {
+            in_nodes[nbr].push(node);
+        }
+    }
+    return in_nodes;
+}
+
+// Merges in and out edges into a sorted, deduplicated adjacency list without
+// self loops.
+fn undirected(out_nodes: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut adj = out_nodes.to_vec();
+    for (node, nbrs) in out_nodes.iter().enumerate() {
+        for &nbr in nbrs {
+            adj[nbr].push(node);
+        }
+    }
+    for (node, nbrs) in adj.iter_mut().enumerate() {
+        nbrs.sort_unstable();
+        nbrs.dedup();
+        nbrs.retain(|&nbr| nbr != node);
+    }
+    return adj;
+}
+
+// order[i] = n means that node n goes to position i. Reorderings return the
+// inverse so that perm[n] = i gives the new position of node n.
+fn invert(order: &[usize]) -> Vec<usize> {
+    let mut perm = vec![0; order.len()];
+    for (i, &node) in order.iter().enumerate() {
+        perm[node] = i;
+    }
+    return perm;
+}
+
+// perm[n] = i means that node n is moved to position i, so a valid permutation
+// must map 0..n_nodes onto itself exactly once.
+pub fn validate_permutation(perm: &[usize], n_nodes: usize) -> Result<()> {
+    if perm.len() != n_nodes {
+        return Err(Error::InvalidPermutation(format!(
+            "expected length {}, got {}",
+            n_nodes,
+            perm.len()
+        )));
+    }
+
+    let mut seen = vec![false; n_nodes];
+    for (node, &pos) in perm.iter().enumerate() {
+        if pos >= n_nodes {
+            return Err(Error::InvalidPermutation(format!(
+                "node {} is mapped to position {} which is out of range",
+                node, pos
+            )));
+        }
+        if seen[pos] {
+            return Err(Error::InvalidPermutation(format!(
+                "position {} is assigned to more than one node",
+                pos
+            )));
+        }
+        seen[pos] = true;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_permutation() {
+        assert!(validate_permutation(&[], 0).is_ok());
+        assert!(validate_permutation(&[2, 0, 1], 3).is_ok());
+
+        assert!(matches!(
+            validate_permutation(&[0, 1], 3),
+            Err(Error::InvalidPermutation(_))
+        ));
+        assert!(matches!(
+            validate_permutation(&[0, 3, 1], 3),
+            Err(Error::InvalidPermutation(_))
+        ));
+        assert!(matches!(
+            validate_permutation(&[0, 1, 1], 3),
+            Err(Error::InvalidPermutation(_))
+        ));
+    }
 }
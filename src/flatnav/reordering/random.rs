@@ -0,0 +1,45 @@
+use super::{check_out_nodes, Reordering};
+use crate::flatnav::error::Result;
+use rand::{seq::SliceRandom, SeedableRng};
+
+// Random shuffle, useful as a baseline for how much locality the other
+// reorderings recover.
+pub struct RandomOrder {
+    seed: u64,
+}
+
+impl RandomOrder {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Reordering for RandomOrder {
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+        check_out_nodes(out_nodes)?;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed);
+        let mut perm: Vec<usize> = (0..out_nodes.len()).collect();
+        perm.shuffle(&mut rng);
+
+        return Ok(perm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::reordering::validate_permutation;
+
+    #[test]
+    fn test_random_order() {
+        let out_nodes = vec![vec![]; 100];
+
+        let perm = RandomOrder::new(3).reorder(&out_nodes).unwrap();
+        validate_permutation(&perm, 100).unwrap();
+        assert_ne!(perm, (0..100).collect::<Vec<_>>());
+
+        assert_eq!(perm, RandomOrder::new(3).reorder(&out_nodes).unwrap());
+        assert_ne!(perm, RandomOrder::new(4).reorder(&out_nodes).unwrap());
+    }
+}
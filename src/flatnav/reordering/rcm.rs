@@ -0,0 +1,84 @@
+use super::{check_out_nodes, invert, undirected, Reordering};
+use crate::flatnav::error::Result;
+use std::collections::VecDeque;
+
+pub struct ReverseCuthillMckee;
+
+impl ReverseCuthillMckee {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for ReverseCuthillMckee {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reordering for ReverseCuthillMckee {
+    fn reorder(&self, out_nodes: &[Vec<usize>]) -> Result<Vec<usize>> {
+        check_out_nodes(out_nodes)?;
+        let adj = undirected(out_nodes);
+
+        let mut order = Vec::with_capacity(out_nodes.len());
+        let mut visited = vec![false; out_nodes.len()];
+        let mut queue = VecDeque::new();
+
+        // Each component is started from its lowest degree node, ties broken
+        // by id, which approximates a peripheral node.
+        let mut roots: Vec<usize> = (0..out_nodes.len()).collect();
+        roots.sort_by_key(|&node| adj[node].len());
+
+        let mut nbrs: Vec<usize> = Vec::new();
+
+        for root in roots {
+            if visited[root] {
+                continue;
+            }
+            visited[root] = true;
+            queue.push_back(root);
+
+            while let Some(node) = queue.pop_front() {
+                order.push(node);
+
+                nbrs.clear();
+                nbrs.extend(adj[node].iter().filter(|&&nbr| !visited[nbr]));
+                nbrs.sort_by_key(|&nbr| adj[nbr].len());
+
+                for &nbr in &nbrs {
+                    visited[nbr] = true;
+                    queue.push_back(nbr);
+                }
+            }
+        }
+
+        order.reverse();
+
+        return Ok(invert(&order));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rcm() {
+        // A path 0 - 3 - 1 - 4 - 2 with shuffled ids plus a hub 5 attached to 1.
+        let out_nodes = vec![vec![3], vec![3, 4], vec![4], vec![], vec![], vec![1]];
+
+        let perm = ReverseCuthillMckee::new().reorder(&out_nodes).unwrap();
+
+        assert_eq!(invert(&perm), vec![2, 4, 5, 1, 3, 0]);
+
+        let bandwidth = out_nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(u, nbrs)| nbrs.iter().map(move |&v| (u, v)))
+            .map(|(u, v)| perm[u].abs_diff(perm[v]))
+            .max()
+            .unwrap();
+        assert_eq!(bandwidth, 2);
+    }
+}
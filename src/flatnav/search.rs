@@ -0,0 +1,152 @@
+use super::distance::Distance;
+use super::priority_queue::{Closest, ClosestQueue, Furthest, FurthestQueue};
+use std::collections::{BinaryHeap, HashSet};
+
+// The parts of a graph that the search and neighbor selection algorithms need,
+// so that they can be shared between Index and ConcurrentIndex. Nodes passed
+// in must be nodes of the graph, this is not checked in release builds.
+pub(crate) trait Graph<NbrT, DataT> {
+    fn max_nbrs(&self) -> usize;
+
+    fn data(&self, node: usize) -> &[DataT];
+
+    // Calls f with the neighbor list of the node. Implementations that can be
+    // modified concurrently must not let f see a partially updated list.
+    fn with_nbrs<R, F: FnOnce(&[NbrT]) -> R>(&self, node: usize, f: F) -> R;
+}
+
+pub(crate) fn beam_search<NbrT, DataT, G, D>(
+    graph: &G,
+    distance_fn: &D,
+    query: &[DataT],
+    entry: NbrT,
+    ef_search: usize,
+) -> ClosestQueue<NbrT>
+where
+    NbrT: num_traits::PrimInt + num_traits::AsPrimitive<usize> + std::hash::Hash,
+    G: Graph<NbrT, DataT>,
+    D: Distance<DataT>,
+{
+    let mut visited: HashSet<NbrT> = HashSet::new();
+    let mut candidates: ClosestQueue<NbrT> = BinaryHeap::new();
+    let mut worklist: FurthestQueue<NbrT> = BinaryHeap::with_capacity(ef_search + 1);
+
+    let dist = distance_fn.call(query, graph.data(entry.as_()));
+    visited.insert(entry);
+    candidates.push(Closest { node: entry, dist });
+    worklist.push(Furthest { node: entry, dist });
+
+    while !candidates.is_empty() {
+        let best_candidate = candidates.pop().unwrap();
+        let worst_nbr = worklist.peek().unwrap();
+
+        if best_candidate.dist > worst_nbr.dist {
+            break;
+        }
+
+        graph.with_nbrs(best_candidate.node.as_(), |nbrs| {
+            for &nbr in nbrs {
+                if nbr != NbrT::max_value() && !visited.contains(&nbr) {
+                    visited.insert(nbr);
+                    let dist = distance_fn.call(query, graph.data(nbr.as_()));
+                    candidates.push(Closest { node: nbr, dist });
+                    worklist.push(Furthest { node: nbr, dist });
+                    if worklist.len() > ef_search {
+                        worklist.pop();
+                    }
+                }
+            }
+        });
+    }
+
+    let mut queue: ClosestQueue<NbrT> = BinaryHeap::with_capacity(worklist.len());
+    worklist.drain().for_each(|x| {
+        queue.push(Closest {
+            node: x.node,
+            dist: x.dist,
+        })
+    });
+    return queue;
+}
+
+// Keeps a candidate only if it is closer to the node than to every neighbor
+// selected so far. The result is padded with NbrT::max_value() to max_nbrs.
+pub(crate) fn select_neighbors<NbrT, DataT, G, D>(
+    graph: &G,
+    distance_fn: &D,
+    mut candidates: ClosestQueue<NbrT>,
+) -> Vec<NbrT>
+where
+    NbrT: num_traits::PrimInt + num_traits::AsPrimitive<usize>,
+    G: Graph<NbrT, DataT>,
+    D: Distance<DataT>,
+{
+    let max_nbrs = graph.max_nbrs();
+
+    if candidates.len() < max_nbrs {
+        let mut selected: Vec<NbrT> = candidates.drain().map(|x| x.node).collect();
+        selected.resize(max_nbrs, NbrT::max_value());
+        return selected;
+    }
+
+    let mut selected: Vec<NbrT> = Vec::with_capacity(max_nbrs);
+
+    while !candidates.is_empty() && selected.len() < max_nbrs {
+        let best_candidate = candidates.pop().unwrap();
+
+        let keep = selected.iter().all(|&choosen_nbr| {
+            distance_fn.call(
+                graph.data(best_candidate.node.as_()),
+                graph.data(choosen_nbr.as_()),
+            ) >= best_candidate.dist
+        });
+
+        if keep {
+            selected.push(best_candidate.node);
+        }
+    }
+
+    selected.resize(max_nbrs, NbrT::max_value());
+    return selected;
+}
+
+// Adds new_node to nbrs, the neighbor list of node. It takes the first empty
+// slot, and if there is none the list is selected again from its current
+// neighbors and new_node. nbrs is passed in rather than read from the graph so
+// that callers can hold it locked or borrowed mutably.
+pub(crate) fn add_neighbor<NbrT, DataT, G, D>(
+    graph: &G,
+    distance_fn: &D,
+    node: usize,
+    nbrs: &mut [NbrT],
+    new_node: NbrT,
+    new_data: &[DataT],
+) where
+    NbrT: num_traits::PrimInt + num_traits::AsPrimitive<usize>,
+    G: Graph<NbrT, DataT>,
+    D: Distance<DataT>,
+{
+    if let Some(slot) = nbrs.iter_mut().find(|x| **x == NbrT::max_value()) {
+        *slot = new_node;
+        return;
+    }
+
+    let node_data = graph.data(node);
+
+    let mut candidates: ClosestQueue<NbrT> = BinaryHeap::with_capacity(nbrs.len() + 1);
+
+    candidates.push(Closest {
+        node: new_node,
+        dist: distance_fn.call(new_data, node_data),
+    });
+
+    for &nbr in nbrs.iter() {
+        candidates.push(Closest {
+            node: nbr,
+            dist: distance_fn.call(graph.data(nbr.as_()), node_data),
+        });
+    }
+
+    let selected = select_neighbors(graph, distance_fn, candidates);
+    nbrs.copy_from_slice(&selected);
+}
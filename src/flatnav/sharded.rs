@@ -0,0 +1,508 @@
+use super::distance::Distance;
+use super::error::{Error, Result};
+use super::flat::FlatIndex;
+use super::index::Index;
+use super::kmeans::kmeans;
+use super::storage::{read_u32, read_u64, LabelT};
+use rayon::prelude::*;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"FNSHARDS";
+const VERSION: u32 = 1;
+const KMEANS_ITERS: usize = 25;
+
+// Index split into independent graph shards which are searched in parallel.
+// Vectors are assigned to a shard either by a hash of their label or to the
+// shard of their nearest centroid. With centroids, queries can be limited to
+// the shards with the closest centroids, trading recall for latency, while
+// with hashing every shard has to be searched.
+pub struct ShardedIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    shards: Vec<Index<NbrT, DataT, DistanceFn>>,
+    // Centroid i is labelled i.
+    centroids: Option<FlatIndex<DataT, DistanceFn>>,
+}
+
+impl<NbrT, DataT, DistanceFn> ShardedIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded
+        + Send
+        + Sync,
+    DataT: num_traits::Num + Copy + Send + Sync,
+    DistanceFn: Distance<DataT> + Clone + Send + Sync,
+{
+    // Assigns vectors to shards by a hash of their label.
+    pub fn new(
+        n_shards: usize,
+        max_nbrs: usize,
+        data_dim: usize,
+        distance_fn: DistanceFn,
+    ) -> Result<Self> {
+        if n_shards == 0 {
+            return Err(Error::InvalidArgument(
+                "a sharded index needs at least one shard".to_string(),
+            ));
+        }
+        return Ok(Self {
+            shards: (0..n_shards)
+                .map(|_| Index::new(max_nbrs, data_dim, 0, distance_fn.clone()))
+                .collect(),
+            centroids: None,
+        });
+    }
+
+    // Creates one shard per centroid and assigns vectors to the shard of their
+    // nearest centroid.
+    pub fn with_centroids(
+        centroids: &[Vec<DataT>],
+        max_nbrs: usize,
+        data_dim: usize,
+        distance_fn: DistanceFn,
+    ) -> Result<Self> {
+        if centroids.is_empty() {
+            return Err(Error::InvalidArgument(
+                "a sharded index needs at least one centroid".to_string(),
+            ));
+        }
+        let mut index = Self::new(centroids.len(), max_nbrs, data_dim, distance_fn.clone())?;
+        let mut flat = FlatIndex::new(data_dim, centroids.len(), distance_fn);
+        for (shard, centroid) in centroids.iter().enumerate() {
+            flat.try_insert(shard as LabelT, centroid, 0)?;
+        }
+        index.centroids = Some(flat);
+        return Ok(index);
+    }
+
+    // IVF style index with one shard per k-means cluster of the sample. Queries
+    // then probe the shards of the n_probe closest clusters, which bounds the
+    // work per query independently of ef_search.
+    pub fn train(
+        sample: &[Vec<DataT>],
+        n_clusters: usize,
+        max_nbrs: usize,
+        distance_fn: DistanceFn,
+    ) -> Result<Self>
+    where
+        DataT: num_traits::NumCast + num_traits::AsPrimitive<f32>,
+    {
+        let centroids = kmeans(sample, n_clusters, KMEANS_ITERS, 0, &distance_fn)?;
+        return Self::with_centroids(&centroids, max_nbrs, sample[0].len(), distance_fn);
+    }
+
+    fn check_dim(&self, data: &[DataT]) -> Result<()> {
+        if data.len() != self.data_dim() {
+            return Err(Error::DimensionMismatch {
+                expected: self.data_dim(),
+                got: data.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    // The shard a vector is inserted into.
+    pub fn shard_of(&self, label: LabelT, data: &[DataT]) -> Result<usize> {
+        self.check_dim(data)?;
+        match &self.centroids {
+            Some(centroids) => Ok(centroids.try_query(data, 0, 1)?[0].0 as usize),
+            None => Ok(hash_shard(label, self.shards.len())),
+        }
+    }
+
+    pub fn try_insert(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_with_payload(label, data, &[], ef_construction);
+    }
+
+    pub fn try_insert_with_payload(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        ef_construction: usize,
+    ) -> Result<()> {
+        let shard = self.shard_of(label, data)?;
+        return self.shards[shard].try_insert_with_payload(label, data, payload, ef_construction);
+    }
+
+    pub fn insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) {
+        if let Err(e) = self.try_insert(label, data, ef_construction) {
+            panic!("{}", e);
+        }
+    }
+
+    // Searches every shard.
+    pub fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        return self.try_query_shards(query, (0..self.shards.len()).collect(), ef_search, topk);
+    }
+
+    // Searches the n_probe shards with the closest centroids. Without
+    // centroids every shard is searched.
+    pub fn try_query_probe(
+        &self,
+        query: &[DataT],
+        n_probe: usize,
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+        let shards = match &self.centroids {
+            Some(centroids) => centroids
+                .try_query(query, 0, n_probe)?
+                .into_iter()
+                .map(|(shard, _)| shard as usize)
+                .collect(),
+            None => (0..self.shards.len()).collect(),
+        };
+        return self.try_query_shards(query, shards, ef_search, topk);
+    }
+
+    // Queries the shards on the rayon thread pool and merges their results.
+    fn try_query_shards(
+        &self,
+        query: &[DataT],
+        shards: Vec<usize>,
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        self.check_dim(query)?;
+
+        let results: Vec<Result<Vec<(LabelT, f32)>>> = shards
+            .par_iter()
+            .map(|&shard| self.shards[shard].try_query(query, ef_search, topk))
+            .collect();
+
+        let mut merged = Vec::new();
+        let mut found = false;
+        for result in results {
+            match result {
+                Ok(results) => {
+                    merged.extend(results);
+                    found = true;
+                }
+                Err(Error::EmptyIndex) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if !found {
+            return Err(Error::EmptyIndex);
+        }
+
+        merged.sort_by(|a, b| a.1.total_cmp(&b.1));
+        merged.truncate(topk);
+        return Ok(merged);
+    }
+
+    pub fn query(&self, query: &[DataT], ef_search: usize, topk: usize) -> Vec<(LabelT, f32)> {
+        match self.try_query(query, ef_search, topk) {
+            Ok(results) => results,
+            Err(Error::EmptyIndex) => Vec::new(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // Removes all vectors with the label and returns whether there were any.
+    pub fn remove(&mut self, label: LabelT) -> bool {
+        if self.centroids.is_none() {
+            let shard = hash_shard(label, self.shards.len());
+            return self.shards[shard].remove(label);
+        }
+        let mut found = false;
+        for shard in &mut self.shards {
+            found |= shard.remove(label);
+        }
+        return found;
+    }
+
+    pub fn n_shards(&self) -> usize {
+        return self.shards.len();
+    }
+
+    pub fn shard(&self, shard: usize) -> &Index<NbrT, DataT, DistanceFn> {
+        return &self.shards[shard];
+    }
+
+    // File layout:
+    // [Magic][Version][n_shards][Has centroids][Centroids as a FlatIndex]
+    // [Each shard as an Index]
+    pub fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.shards.len() as u64).to_le_bytes())?;
+        match &self.centroids {
+            Some(centroids) => {
+                writer.write_all(&[1])?;
+                centroids.save(writer)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        for shard in &self.shards {
+            shard.save(writer)?;
+        }
+        return Ok(());
+    }
+
+    pub fn load<R: Read>(reader: &mut R, distance_fn: DistanceFn) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidFormat(
+                "not a flatnav sharded index".to_string(),
+            ));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(Error::InvalidFormat(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let n_shards = read_u64(reader)? as usize;
+        if n_shards == 0 {
+            return Err(Error::InvalidFormat("index has no shards".to_string()));
+        }
+
+        let mut has_centroids = [0];
+        reader.read_exact(&mut has_centroids)?;
+        let centroids = if has_centroids[0] != 0 {
+            let centroids = FlatIndex::load(reader, distance_fn.clone())?;
+            if centroids.len() != n_shards {
+                return Err(Error::InvalidFormat(format!(
+                    "{} centroids for {} shards",
+                    centroids.len(),
+                    n_shards
+                )));
+            }
+            Some(centroids)
+        } else {
+            None
+        };
+
+        // n_shards comes from the file, so the shards are not preallocated and
+        // a corrupted count fails at the end of the input.
+        let mut shards: Vec<Index<NbrT, DataT, DistanceFn>> = Vec::new();
+        for _ in 0..n_shards {
+            let shard = Index::load(reader, distance_fn.clone())?;
+            if let Some(first) = shards.first() {
+                if shard.data_dim() != first.data_dim() || shard.max_nbrs() != first.max_nbrs() {
+                    return Err(Error::InvalidFormat(format!(
+                        "shard {} has data dim {} and max_nbrs {}, expected {} and {}",
+                        shards.len(),
+                        shard.data_dim(),
+                        shard.max_nbrs(),
+                        first.data_dim(),
+                        first.max_nbrs()
+                    )));
+                }
+            }
+            shards.push(shard);
+        }
+        if let Some(centroids) = &centroids {
+            if centroids.data_dim() != shards[0].data_dim() {
+                return Err(Error::InvalidFormat(format!(
+                    "centroids have data dim {}, shards {}",
+                    centroids.data_dim(),
+                    shards[0].data_dim()
+                )));
+            }
+        }
+
+        return Ok(Self { shards, centroids });
+    }
+
+    // Number of vectors, not counting removed ones.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn data_dim(&self) -> usize {
+        self.shards[0].data_dim()
+    }
+}
+
+// SplitMix64 finalizer, so that consecutive labels are spread evenly and the
+// assignment does not depend on the platform or the standard library version.
+fn hash_shard(label: LabelT, n_shards: usize) -> usize {
+    let mut x = label.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    return (x % n_shards as u64) as usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_sharded_index() {
+        let mut index =
+            ShardedIndex::<u32, f32, EuclideanDistance>::new(4, 8, 2, EuclideanDistance).unwrap();
+        assert_eq!(index.try_query(&[0.0, 0.0], 16, 1), Err(Error::EmptyIndex));
+        for i in 0..400 {
+            index.insert(i, &[i as f32, (i % 7) as f32], 32);
+        }
+        assert_eq!(index.len(), 400);
+        assert!((0..4).all(|shard| index.shard(shard).len() > 50));
+
+        let results = index.query(&[200.1, 4.0], 32, 3);
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels, vec![200, 201, 199]);
+        assert!(index.remove(200));
+        assert_eq!(index.query(&[200.1, 4.0], 32, 1)[0].0, 201);
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let loaded = ShardedIndex::<u32, f32, EuclideanDistance>::load(
+            &mut buf.as_slice(),
+            EuclideanDistance,
+        )
+        .unwrap();
+        assert_eq!(loaded.len(), 399);
+        assert_eq!(loaded.query(&[200.1, 4.0], 32, 1)[0].0, 201);
+
+        // Shards of different shapes are rejected.
+        let mut other =
+            ShardedIndex::<u32, f32, EuclideanDistance>::new(1, 8, 3, EuclideanDistance).unwrap();
+        other.insert(0, &[0.0, 0.0, 0.0], 32);
+        let mut shard = Vec::new();
+        other.shard(0).save(&mut shard).unwrap();
+        let mut last = Vec::new();
+        index.shard(3).save(&mut last).unwrap();
+        let end = buf.len() - last.len();
+        let mut mixed = buf[..end].to_vec();
+        mixed.extend_from_slice(&shard);
+        assert!(matches!(
+            ShardedIndex::<u32, f32, EuclideanDistance>::load(
+                &mut mixed.as_slice(),
+                EuclideanDistance
+            ),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_sharded_index_train() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let noise = rand::distr::Uniform::new(-1.0, 1.0).unwrap();
+        let centers: Vec<Vec<f32>> = (0..8)
+            .map(|_| (0..8).map(|_| rng.sample(noise) * 20.0).collect())
+            .collect();
+        let dataset: Vec<Vec<f32>> = (0..2000)
+            .map(|i| {
+                centers[i % 8]
+                    .iter()
+                    .map(|x| x + rng.sample(noise))
+                    .collect()
+            })
+            .collect();
+
+        let mut index = ShardedIndex::<u16, f32, EuclideanDistance>::train(
+            &dataset[..400],
+            8,
+            8,
+            EuclideanDistance,
+        )
+        .unwrap();
+        assert_eq!(index.n_shards(), 8);
+        for (i, vec) in dataset.iter().enumerate() {
+            index.insert(i as u64, vec, 32);
+        }
+        assert!((0..8).all(|shard| index.shard(shard).len() == 250));
+
+        let recall = |n_probe: usize| {
+            dataset
+                .iter()
+                .enumerate()
+                .filter(|(i, vec)| {
+                    index.try_query_probe(vec, n_probe, 32, 1).unwrap()[0].0 == *i as u64
+                })
+                .count()
+        };
+        assert!(recall(1) >= 1990, "recall {}", recall(1));
+    }
+
+    #[test]
+    fn test_sharded_index_centroids() {
+        let centroids = vec![vec![0.0, 0.0], vec![100.0, 0.0], vec![0.0, 100.0]];
+        let mut index = ShardedIndex::<u32, f32, EuclideanDistance>::with_centroids(
+            &centroids,
+            8,
+            2,
+            EuclideanDistance,
+        )
+        .unwrap();
+        for i in 0..300 {
+            let center = &centroids[i % 3];
+            let offset = (i / 3) as f32 / 10.0;
+            index.insert(i as u64, &[center[0] + offset, center[1]], 32);
+        }
+        assert_eq!(index.shard_of(0, &[90.0, 10.0]).unwrap(), 1);
+        assert!((0..3).all(|shard| index.shard(shard).len() == 100));
+
+        let results = index.try_query_probe(&[100.52, 0.0], 1, 32, 2).unwrap();
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels, vec![16, 19]);
+        assert_eq!(
+            index.try_query_probe(&[0.0], 1, 32, 2),
+            Err(Error::DimensionMismatch {
+                expected: 2,
+                got: 1
+            })
+        );
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        let loaded = ShardedIndex::<u32, f32, EuclideanDistance>::load(
+            &mut buf.as_slice(),
+            EuclideanDistance,
+        )
+        .unwrap();
+        assert_eq!(loaded.shard_of(0, &[10.0, 90.0]).unwrap(), 2);
+        assert_eq!(
+            loaded.try_query_probe(&[100.52, 0.0], 1, 32, 2).unwrap(),
+            results
+        );
+
+        assert!(matches!(
+            ShardedIndex::<u32, f32, EuclideanDistance>::with_centroids(
+                &[],
+                8,
+                2,
+                EuclideanDistance
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            ShardedIndex::<u32, f32, EuclideanDistance>::new(0, 8, 2, EuclideanDistance),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
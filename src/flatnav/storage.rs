@@ -1,10 +1,25 @@
+use super::checksum::{ChecksumReader, ChecksumWriter};
+use super::error::{Error, Result};
+use super::payload::PayloadColumn;
+use super::search::Graph;
+use std::io::{Read, Write};
 use std::{marker::PhantomData, ptr};
 
 pub type LabelT = u64;
 
+const MAGIC: &[u8; 8] = b"FLATNAV\0";
+const VERSION: u32 = 1;
+
 // Layout of the data buffer:
 // [Label 1][Neighbors 1][Data 1][Label 2][Neighbors 2][Data 2]...
-pub struct InMemStorage<NbrT, DataT>
+// Payloads are variable length so they are kept in a separate column, which is
+// only allocated once the first non empty payload is added. Likewise the
+// namespace column is only allocated once a node is added outside of the
+// default namespace 0, and the removed column once a node is removed.
+// Removed nodes keep their place in the graph so that searches can still pass
+// through them, they are only skipped in results.
+#[derive(Clone)]
+pub(crate) struct InMemStorage<NbrT, DataT>
 where
     NbrT: Copy
         + num_traits::AsPrimitive<usize>
@@ -20,6 +35,10 @@ where
     data_dim: usize,
     node_size: usize,
     data: Vec<u8>,
+    payloads: Option<PayloadColumn>,
+    namespaces: Option<Vec<u32>>,
+    removed: Option<Vec<bool>>,
+    n_removed: usize,
     _a: PhantomData<NbrT>,
     _b: PhantomData<DataT>,
 }
@@ -33,40 +52,65 @@ where
         + std::cmp::PartialEq,
     DataT: Copy,
 {
-    pub fn new(max_nbrs: usize, data_dim: usize, capacity: usize) -> Self {
+    pub(crate) fn new(max_nbrs: usize, data_dim: usize, capacity: usize) -> Self {
         let node_size = Self::node_size(max_nbrs, data_dim);
         Self {
             n_nodes: 0,
             nbr_offset: Self::nbr_offset(),
-            max_nbrs: max_nbrs,
+            max_nbrs,
             data_offset: Self::data_offset(max_nbrs),
-            data_dim: data_dim,
-            node_size: node_size,
+            data_dim,
+            node_size,
             data: Vec::with_capacity(node_size * capacity),
+            payloads: None,
+            namespaces: None,
+            removed: None,
+            n_removed: 0,
             _a: PhantomData,
             _b: PhantomData,
         }
     }
 
-    fn nbr_offset() -> usize {
+    pub(super) fn nbr_offset() -> usize {
         return pad_to(std::mem::size_of::<LabelT>(), std::mem::align_of::<NbrT>());
     }
 
-    fn data_offset(max_nbrs: usize) -> usize {
+    pub(super) fn data_offset(max_nbrs: usize) -> usize {
         return pad_to(
             Self::nbr_offset() + max_nbrs * std::mem::size_of::<NbrT>(),
             std::mem::align_of::<DataT>(),
         );
     }
 
-    fn node_size(max_nbrs: usize, data_len: usize) -> usize {
+    pub(super) fn node_size(max_nbrs: usize, data_len: usize) -> usize {
         return pad_to(
             Self::data_offset(max_nbrs) + data_len * std::mem::size_of::<DataT>(),
             std::mem::align_of::<LabelT>(),
         );
     }
 
-    pub fn label(&self, node: usize) -> LabelT {
+    // node_size for sizes read from a file, which are rejected instead of
+    // overflowing.
+    pub(super) fn checked_node_size(max_nbrs: usize, data_dim: usize) -> Result<usize> {
+        let nbrs_size = max_nbrs.checked_mul(std::mem::size_of::<NbrT>());
+        let data_len = data_dim.checked_mul(std::mem::size_of::<DataT>());
+        let record_size = nbrs_size
+            .zip(data_len)
+            .and_then(|(a, b)| a.checked_add(b))
+            .and_then(|size| size.checked_add(64));
+        if record_size.is_none() {
+            return Err(Error::InvalidFormat(format!(
+                "invalid max_nbrs {} or data_dim {}",
+                max_nbrs, data_dim
+            )));
+        }
+        return Ok(Self::node_size(max_nbrs, data_dim));
+    }
+
+    // The node accessors only check that node < len() in debug builds, so they
+    // are kept private to the crate where every node id is already known to be
+    // in range.
+    pub(crate) fn label(&self, node: usize) -> LabelT {
         debug_assert!(node < self.n_nodes);
         let offset = node * self.node_size;
         let label = unsafe { std::ptr::read(self.data.as_ptr().add(offset) as *const LabelT) };
@@ -80,7 +124,7 @@ where
         unsafe { std::ptr::write(self.data.as_mut_ptr().add(offset) as *mut LabelT, label) };
     }
 
-    pub fn nbrs(&self, node: usize) -> &[NbrT] {
+    pub(crate) fn nbrs(&self, node: usize) -> &[NbrT] {
         debug_assert!(node < self.n_nodes);
         let offset = self.nbr_offset + node * self.node_size;
         let nbrs = unsafe {
@@ -89,7 +133,7 @@ where
         return nbrs;
     }
 
-    pub fn nbrs_mut(&mut self, node: usize) -> &mut [NbrT] {
+    pub(crate) fn nbrs_mut(&mut self, node: usize) -> &mut [NbrT] {
         debug_assert!(node < self.n_nodes);
         let offset = self.nbr_offset + node * self.node_size;
         let nbrs = unsafe {
@@ -101,7 +145,7 @@ where
         return nbrs;
     }
 
-    pub fn data(&self, node: usize) -> &[DataT] {
+    pub(crate) fn data(&self, node: usize) -> &[DataT] {
         debug_assert!(node < self.n_nodes);
         let offset = self.data_offset + node * self.node_size;
         let data = unsafe {
@@ -125,25 +169,57 @@ where
         return data;
     }
 
-    pub fn add_node(&mut self, label: LabelT, nbrs: &[NbrT], data: &[DataT]) -> usize {
+    // The raw [Label][Neighbors][Data] record of the node.
+    pub(super) fn node_bytes(&self, node: usize) -> &[u8] {
+        debug_assert!(node < self.n_nodes);
+        return &self.data[node * self.node_size..(node + 1) * self.node_size];
+    }
+
+    #[cfg(test)]
+    pub(crate) fn add_node(
+        &mut self,
+        label: LabelT,
+        nbrs: &[NbrT],
+        data: &[DataT],
+    ) -> Result<usize> {
+        return self.add_node_with_payload(label, nbrs, data, &[]);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn add_node_with_payload(
+        &mut self,
+        label: LabelT,
+        nbrs: &[NbrT],
+        data: &[DataT],
+        payload: &[u8],
+    ) -> Result<usize> {
+        return self.add_node_in_namespace(0, label, nbrs, data, payload);
+    }
+
+    pub(crate) fn add_node_in_namespace(
+        &mut self,
+        namespace: u32,
+        label: LabelT,
+        nbrs: &[NbrT],
+        data: &[DataT],
+        payload: &[u8],
+    ) -> Result<usize> {
         if data.len() != self.data_dim {
-            panic!(
-                "data dim mismatch: expected {}, got {}",
-                self.data_dim,
-                data.len()
-            );
+            return Err(Error::DimensionMismatch {
+                expected: self.data_dim,
+                got: data.len(),
+            });
         }
 
         if nbrs.len() != self.max_nbrs {
-            panic!(
-                "neighbor count mismatch: expected {}, got {}",
-                self.max_nbrs,
-                nbrs.len()
-            );
+            return Err(Error::NeighborCountMismatch {
+                expected: self.max_nbrs,
+                got: nbrs.len(),
+            });
         }
 
         let new_id = self.n_nodes;
-        self.n_nodes = self.n_nodes + 1;
+        self.n_nodes += 1;
 
         if self.data.len() <= self.n_nodes * self.node_size {
             self.data.resize(self.n_nodes * self.node_size, 0);
@@ -153,18 +229,77 @@ where
         self.nbrs_mut(new_id).copy_from_slice(nbrs);
         self.data_mut(new_id).copy_from_slice(data);
 
-        return new_id;
+        if self.payloads.is_none() && !payload.is_empty() {
+            self.payloads = Some(PayloadColumn::with_empty(new_id));
+        }
+        if let Some(payloads) = &mut self.payloads {
+            payloads.push(payload);
+        }
+
+        if self.namespaces.is_none() && namespace != 0 {
+            self.namespaces = Some(vec![0; new_id]);
+        }
+        if let Some(namespaces) = &mut self.namespaces {
+            namespaces.push(namespace);
+        }
+        if let Some(removed) = &mut self.removed {
+            removed.push(false);
+        }
+
+        return Ok(new_id);
+    }
+
+    pub(crate) fn payload(&self, node: usize) -> &[u8] {
+        debug_assert!(node < self.n_nodes);
+        return match &self.payloads {
+            Some(payloads) => payloads.get(node),
+            None => &[],
+        };
+    }
+
+    pub(crate) fn namespace(&self, node: usize) -> u32 {
+        return match &self.namespaces {
+            Some(namespaces) => namespaces[node],
+            None => 0,
+        };
+    }
+
+    // Returns false if the node was already removed.
+    pub(crate) fn remove(&mut self, node: usize) -> bool {
+        let n_nodes = self.n_nodes;
+        let removed = self.removed.get_or_insert_with(|| vec![false; n_nodes]);
+        if removed[node] {
+            return false;
+        }
+        removed[node] = true;
+        self.n_removed += 1;
+        return true;
+    }
+
+    pub(crate) fn is_removed(&self, node: usize) -> bool {
+        return match &self.removed {
+            Some(removed) => removed[node],
+            None => false,
+        };
     }
 
-    pub fn len(&self) -> usize {
+    pub(crate) fn n_removed(&self) -> usize {
+        return self.n_removed;
+    }
+
+    pub(crate) fn len(&self) -> usize {
         return self.n_nodes;
     }
 
-    pub fn max_nbrs(&self) -> usize {
+    pub(crate) fn is_empty(&self) -> bool {
+        return self.n_nodes == 0;
+    }
+
+    pub(crate) fn max_nbrs(&self) -> usize {
         return self.max_nbrs;
     }
 
-    pub fn data_dim(&self) -> usize {
+    pub(crate) fn data_dim(&self) -> usize {
         return self.data_dim;
     }
 
@@ -181,7 +316,7 @@ where
         }
     }
 
-    pub fn reorder(&mut self, perm: &[usize]) {
+    pub(crate) fn reorder(&mut self, perm: &[usize]) {
         // perm[n] = i means that node n is moved to pos i
 
         for node in 0..self.len() {
@@ -203,10 +338,202 @@ where
                 dest = perm[dest];
             }
         }
+
+        if let Some(payloads) = &mut self.payloads {
+            payloads.reorder(perm);
+        }
+
+        if let Some(namespaces) = &mut self.namespaces {
+            let mut reordered = vec![0; namespaces.len()];
+            for (node, &namespace) in namespaces.iter().enumerate() {
+                reordered[perm[node]] = namespace;
+            }
+            *namespaces = reordered;
+        }
+
+        if let Some(removed) = &mut self.removed {
+            let mut reordered = vec![false; removed.len()];
+            for (node, &is_removed) in removed.iter().enumerate() {
+                reordered[perm[node]] = is_removed;
+            }
+            *removed = reordered;
+        }
+    }
+
+    // File layout:
+    // [Magic][Version][NbrT size][DataT size][max_nbrs][data_dim][n_nodes][Node buffer]
+    // [Has payloads][Payload column][Has namespaces][Namespace u32 for each node]
+    // [Has removed][Removed u8 for each node]
+    // Each of the header, the node buffer and the three optional sections is
+    // followed by the CRC-32 of its bytes, so that truncated or corrupted files
+    // are rejected on load.
+    // The header is little endian, the node buffer is written as is and so is
+    // only portable between hosts with the same endianness.
+    pub(crate) fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let writer = &mut ChecksumWriter::new(writer);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(std::mem::size_of::<NbrT>() as u32).to_le_bytes())?;
+        writer.write_all(&(std::mem::size_of::<DataT>() as u32).to_le_bytes())?;
+        writer.write_all(&(self.max_nbrs as u64).to_le_bytes())?;
+        writer.write_all(&(self.data_dim as u64).to_le_bytes())?;
+        writer.write_all(&(self.n_nodes as u64).to_le_bytes())?;
+        writer.write_checksum()?;
+        writer.write_all(&self.data[..self.n_nodes * self.node_size])?;
+        writer.write_checksum()?;
+        match &self.payloads {
+            Some(payloads) => {
+                writer.write_all(&[1])?;
+                payloads.save(writer)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        writer.write_checksum()?;
+        match &self.namespaces {
+            Some(namespaces) => {
+                writer.write_all(&[1])?;
+                for namespace in namespaces {
+                    writer.write_all(&namespace.to_le_bytes())?;
+                }
+            }
+            None => writer.write_all(&[0])?,
+        }
+        writer.write_checksum()?;
+        match &self.removed {
+            Some(removed) => {
+                writer.write_all(&[1])?;
+                let bytes: Vec<u8> = removed.iter().map(|&x| x as u8).collect();
+                writer.write_all(&bytes)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        writer.write_checksum()?;
+        return Ok(());
+    }
+
+    pub(crate) fn load<R: Read>(reader: &mut R) -> Result<Self> {
+        let reader = &mut ChecksumReader::new(reader);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidFormat("not a flatnav index".to_string()));
+        }
+
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(Error::InvalidFormat(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let nbr_size = read_u32(reader)? as usize;
+        let data_size = read_u32(reader)? as usize;
+        if nbr_size != std::mem::size_of::<NbrT>() || data_size != std::mem::size_of::<DataT>() {
+            return Err(Error::InvalidFormat(format!(
+                "index was saved with {} byte neighbor ids and {} byte data, expected {} and {}",
+                nbr_size,
+                data_size,
+                std::mem::size_of::<NbrT>(),
+                std::mem::size_of::<DataT>()
+            )));
+        }
+
+        let max_nbrs = read_u64(reader)? as usize;
+        let data_dim = read_u64(reader)? as usize;
+        let n_nodes = read_u64(reader)? as usize;
+        reader.verify_checksum("header")?;
+
+        Self::checked_node_size(max_nbrs, data_dim)?;
+        let mut storage = Self::new(max_nbrs, data_dim, 0);
+        let n_bytes = n_nodes
+            .checked_mul(storage.node_size)
+            .ok_or_else(|| Error::InvalidFormat(format!("node count {} is too large", n_nodes)))?;
+
+        storage.data = read_bytes(reader, n_bytes)?;
+        storage.n_nodes = n_nodes;
+        reader.verify_checksum("node buffer")?;
+
+        let mut has_payloads = [0];
+        reader.read_exact(&mut has_payloads)?;
+        if has_payloads[0] != 0 {
+            storage.payloads = Some(PayloadColumn::load(reader, n_nodes)?);
+        }
+        reader.verify_checksum("payloads")?;
+
+        let mut has_namespaces = [0];
+        reader.read_exact(&mut has_namespaces)?;
+        if has_namespaces[0] != 0 {
+            let namespaces = (0..n_nodes)
+                .map(|_| read_u32(reader))
+                .collect::<Result<_>>()?;
+            storage.namespaces = Some(namespaces);
+        }
+        reader.verify_checksum("namespaces")?;
+
+        let mut has_removed = [0];
+        reader.read_exact(&mut has_removed)?;
+        if has_removed[0] != 0 {
+            let removed: Vec<bool> = read_bytes(reader, n_nodes)?
+                .iter()
+                .map(|&x| x != 0)
+                .collect();
+            storage.n_removed = removed.iter().filter(|&&x| x).count();
+            storage.removed = Some(removed);
+        }
+        reader.verify_checksum("removed")?;
+
+        return Ok(storage);
     }
 }
 
-fn pad_to(n: usize, align: usize) -> usize {
+impl<NbrT, DataT> Graph<NbrT, DataT> for InMemStorage<NbrT, DataT>
+where
+    NbrT: Copy
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + num_traits::Bounded
+        + std::cmp::PartialEq,
+    DataT: Copy,
+{
+    fn max_nbrs(&self) -> usize {
+        return self.max_nbrs;
+    }
+
+    fn data(&self, node: usize) -> &[DataT] {
+        return InMemStorage::data(self, node);
+    }
+
+    fn with_nbrs<R, F: FnOnce(&[NbrT]) -> R>(&self, node: usize, f: F) -> R {
+        return f(self.nbrs(node));
+    }
+}
+
+pub(super) fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    return Ok(u32::from_le_bytes(buf));
+}
+
+pub(super) fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    return Ok(u64::from_le_bytes(buf));
+}
+
+// Reads exactly n bytes. The buffer grows with the bytes actually read rather
+// than being allocated up front, so that a corrupted length in a file fails
+// with an error at the end of the file instead of a huge allocation.
+pub(super) fn read_bytes<R: Read>(reader: &mut R, n: usize) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    Read::take(&mut *reader, n as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != n {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    return Ok(bytes);
+}
+
+pub(super) fn pad_to(n: usize, align: usize) -> usize {
     let rem = n % align;
     if rem == 0 {
         return n;
@@ -217,6 +544,7 @@ fn pad_to(n: usize, align: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::flatnav::checksum::crc32;
 
     #[test]
     fn test_storage_basic() {
@@ -224,11 +552,13 @@ mod tests {
 
         assert_eq!(storage.len(), 0);
 
-        let id = storage.add_node(10, &[1, 2, 3, 4], &[10, 20, 30]);
+        let id = storage.add_node(10, &[1, 2, 3, 4], &[10, 20, 30]).unwrap();
         assert_eq!(id, 0);
-        let id = storage.add_node(20, &[5, 6, 7, 8], &[40, 50, 60]);
+        let id = storage.add_node(20, &[5, 6, 7, 8], &[40, 50, 60]).unwrap();
         assert_eq!(id, 1);
-        let id = storage.add_node(30, &[9, 10, 11, 12], &[70, 80, 90]);
+        let id = storage
+            .add_node(30, &[9, 10, 11, 12], &[70, 80, 90])
+            .unwrap();
         assert_eq!(id, 2);
 
         assert_eq!(storage.len(), 3);
@@ -258,7 +588,7 @@ mod tests {
             let data: Vec<i8> = (i..i + DATA_DIM)
                 .map(|x| ((x as i32) - 127) as i8)
                 .collect();
-            storage.add_node(label as u64, &nbrs, &data);
+            storage.add_node(label as u64, &nbrs, &data).unwrap();
         }
 
         for i in 0..200 {
@@ -275,6 +605,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop, clippy::useless_vec)]
     fn test_storage_reorder() {
         const MAX_NBRS: usize = 3;
         const DATA_DIM: usize = 2;
@@ -284,7 +615,7 @@ mod tests {
         for i in 0..N_NODES {
             let nbrs: Vec<_> = (i..i + MAX_NBRS).map(|x| (x % N_NODES) as i8).collect();
             let data = vec![i as u16, (i + 1) as u16];
-            storage.add_node(i as u64, &nbrs, &data);
+            storage.add_node(i as u64, &nbrs, &data).unwrap();
         }
 
         let perm = vec![4, 5, 2, 0, 3, 1];
@@ -308,4 +639,181 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn test_storage_save_load() {
+        let mut storage = InMemStorage::<u32, f32>::new(2, 3, 4);
+        for i in 0..4 {
+            storage
+                .add_node(i * 7, &[i as u32, u32::MAX], &[i as f32, 1.0, -1.0])
+                .unwrap();
+        }
+
+        let mut buf = Vec::new();
+        storage.save(&mut buf).unwrap();
+
+        let loaded = InMemStorage::<u32, f32>::load(&mut buf.as_slice()).unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(loaded.max_nbrs(), 2);
+        assert_eq!(loaded.data_dim(), 3);
+        for i in 0..4 {
+            assert_eq!(loaded.label(i), i as u64 * 7);
+            assert_eq!(loaded.nbrs(i), &[i as u32, u32::MAX]);
+            assert_eq!(loaded.data(i), &[i as f32, 1.0, -1.0]);
+        }
+
+        assert!(matches!(
+            InMemStorage::<u16, f32>::load(&mut buf.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            InMemStorage::<u32, f32>::load(&mut &buf[..buf.len() - 1]),
+            Err(Error::Io(_))
+        ));
+
+        let mut corrupted = buf.clone();
+        corrupted[60] ^= 1;
+        assert_eq!(
+            InMemStorage::<u32, f32>::load(&mut corrupted.as_slice()).err(),
+            Some(Error::InvalidFormat(
+                "checksum mismatch in node buffer".to_string()
+            ))
+        );
+
+        // Other versions are rejected rather than loaded without checksums.
+        let mut corrupted = buf.clone();
+        corrupted[8..12].copy_from_slice(&4u32.to_le_bytes());
+        assert!(matches!(
+            InMemStorage::<u32, f32>::load(&mut corrupted.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        // Corrupted lengths fail instead of allocating what they claim.
+        let mut corrupted = buf.clone();
+        corrupted[36..44].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(
+            InMemStorage::<u32, f32>::load(&mut corrupted.as_slice()).err(),
+            Some(Error::InvalidFormat(
+                "checksum mismatch in header".to_string()
+            ))
+        );
+        // Even with a matching header checksum.
+        let with_header = |offset: usize, value: u64| {
+            let mut corrupted = buf.clone();
+            corrupted[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            let crc = crc32(&corrupted[..44]);
+            corrupted[44..48].copy_from_slice(&crc.to_le_bytes());
+            return corrupted;
+        };
+        for n_nodes in [1u64 << 40, u64::MAX / 2] {
+            let corrupted = with_header(36, n_nodes);
+            assert!(InMemStorage::<u32, f32>::load(&mut corrupted.as_slice()).is_err());
+        }
+        let corrupted = with_header(28, u64::MAX);
+        assert!(matches!(
+            InMemStorage::<u32, f32>::load(&mut corrupted.as_slice()),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_storage_payloads() {
+        let mut storage = InMemStorage::<u32, u8>::new(1, 1, 4);
+        storage.add_node(0, &[1], &[0]).unwrap();
+        storage
+            .add_node_with_payload(1, &[2], &[1], b"one")
+            .unwrap();
+        storage
+            .add_node_with_payload(2, &[0], &[2], b"two")
+            .unwrap();
+
+        assert_eq!(storage.payload(0), b"");
+        assert_eq!(storage.payload(1), b"one");
+
+        storage.reorder(&[2, 0, 1]);
+        assert_eq!(storage.label(0), 1);
+        assert_eq!(storage.payload(0), b"one");
+        assert_eq!(storage.payload(1), b"two");
+        assert_eq!(storage.payload(2), b"");
+
+        let mut buf = Vec::new();
+        storage.save(&mut buf).unwrap();
+        let loaded = InMemStorage::<u32, u8>::load(&mut buf.as_slice()).unwrap();
+        for node in 0..3 {
+            assert_eq!(loaded.label(node), storage.label(node));
+            assert_eq!(loaded.payload(node), storage.payload(node));
+        }
+    }
+
+    #[test]
+    fn test_storage_namespaces() {
+        let mut storage = InMemStorage::<u32, u8>::new(1, 1, 4);
+        storage.add_node(0, &[1], &[0]).unwrap();
+        storage
+            .add_node_in_namespace(7, 1, &[2], &[1], &[])
+            .unwrap();
+        storage
+            .add_node_in_namespace(3, 2, &[0], &[2], b"x")
+            .unwrap();
+        assert_eq!(storage.namespace(0), 0);
+        assert_eq!(storage.namespace(1), 7);
+
+        storage.reorder(&[2, 0, 1]);
+        assert_eq!(storage.namespace(0), 7);
+        assert_eq!(storage.namespace(1), 3);
+        assert_eq!(storage.namespace(2), 0);
+        assert_eq!(storage.payload(1), b"x");
+
+        let mut buf = Vec::new();
+        storage.save(&mut buf).unwrap();
+        let loaded = InMemStorage::<u32, u8>::load(&mut buf.as_slice()).unwrap();
+        for node in 0..3 {
+            assert_eq!(loaded.label(node), storage.label(node));
+            assert_eq!(loaded.namespace(node), storage.namespace(node));
+        }
+    }
+
+    #[test]
+    fn test_storage_remove() {
+        let mut storage = InMemStorage::<u32, u8>::new(1, 1, 4);
+        storage.add_node(0, &[1], &[0]).unwrap();
+        storage.add_node(1, &[2], &[1]).unwrap();
+        assert!(storage.remove(1));
+        assert!(!storage.remove(1));
+        storage.add_node(2, &[0], &[2]).unwrap();
+        assert_eq!(storage.n_removed(), 1);
+        assert!(!storage.is_removed(2));
+
+        storage.reorder(&[2, 0, 1]);
+        assert!(storage.is_removed(0));
+        assert!(!storage.is_removed(2));
+
+        let mut buf = Vec::new();
+        storage.save(&mut buf).unwrap();
+        let loaded = InMemStorage::<u32, u8>::load(&mut buf.as_slice()).unwrap();
+        assert_eq!(loaded.n_removed(), 1);
+        assert!(loaded.is_removed(0));
+        assert!(!loaded.is_removed(1));
+    }
+
+    #[test]
+    fn test_storage_add_node_mismatch() {
+        let mut storage = InMemStorage::<u32, u8>::new(4, 3, 2);
+
+        assert_eq!(
+            storage.add_node(10, &[1, 2, 3, 4], &[10, 20]),
+            Err(Error::DimensionMismatch {
+                expected: 3,
+                got: 2
+            })
+        );
+        assert_eq!(
+            storage.add_node(10, &[1, 2, 3], &[10, 20, 30]),
+            Err(Error::NeighborCountMismatch {
+                expected: 4,
+                got: 3
+            })
+        );
+        assert_eq!(storage.len(), 0);
+    }
 }
@@ -0,0 +1,276 @@
+use super::distance::Distance;
+use super::error::Result;
+use super::flat::FlatIndex;
+use super::index::Index;
+use super::sharded::ShardedIndex;
+use super::storage::LabelT;
+use super::wal::DurableIndex;
+
+// Operations shared by every kind of index so that application code can be
+// generic over them. Errors are always returned, as from the inherent try_
+// methods of the same names.
+pub trait VectorIndex<DataT> {
+    fn try_insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) -> Result<()>;
+
+    fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>>;
+
+    // Removes all vectors with the label and returns whether there were any.
+    fn remove(&mut self, label: LabelT) -> Result<bool>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    fn data_dim(&self) -> usize;
+
+    // Writes the index in the format read by the load function of its type,
+    // or for DurableIndex in its snapshot format.
+    fn save(&self, writer: &mut dyn std::io::Write) -> Result<()>;
+}
+
+impl<NbrT, DataT, DistanceFn> VectorIndex<DataT> for Index<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    fn try_insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) -> Result<()> {
+        return Index::try_insert(self, label, data, ef_construction);
+    }
+
+    fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        return Index::try_query(self, query, ef_search, topk);
+    }
+
+    fn remove(&mut self, label: LabelT) -> Result<bool> {
+        return Ok(Index::remove(self, label));
+    }
+
+    fn len(&self) -> usize {
+        return Index::len(self);
+    }
+
+    fn data_dim(&self) -> usize {
+        return Index::data_dim(self);
+    }
+
+    fn save(&self, mut writer: &mut dyn std::io::Write) -> Result<()> {
+        return Index::save(self, &mut writer);
+    }
+}
+
+impl<DataT, DistanceFn> VectorIndex<DataT> for FlatIndex<DataT, DistanceFn>
+where
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    fn try_insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) -> Result<()> {
+        return FlatIndex::try_insert(self, label, data, ef_construction);
+    }
+
+    fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        return FlatIndex::try_query(self, query, ef_search, topk);
+    }
+
+    fn remove(&mut self, label: LabelT) -> Result<bool> {
+        return Ok(FlatIndex::remove(self, label));
+    }
+
+    fn len(&self) -> usize {
+        return FlatIndex::len(self);
+    }
+
+    fn data_dim(&self) -> usize {
+        return FlatIndex::data_dim(self);
+    }
+
+    fn save(&self, mut writer: &mut dyn std::io::Write) -> Result<()> {
+        return FlatIndex::save(self, &mut writer);
+    }
+}
+
+impl<NbrT, DataT, DistanceFn> VectorIndex<DataT> for ShardedIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded
+        + Send
+        + Sync,
+    DataT: num_traits::Num + Copy + Send + Sync,
+    DistanceFn: Distance<DataT> + Clone + Send + Sync,
+{
+    fn try_insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) -> Result<()> {
+        return ShardedIndex::try_insert(self, label, data, ef_construction);
+    }
+
+    fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        return ShardedIndex::try_query(self, query, ef_search, topk);
+    }
+
+    fn remove(&mut self, label: LabelT) -> Result<bool> {
+        return Ok(ShardedIndex::remove(self, label));
+    }
+
+    fn len(&self) -> usize {
+        return ShardedIndex::len(self);
+    }
+
+    fn data_dim(&self) -> usize {
+        return ShardedIndex::data_dim(self);
+    }
+
+    fn save(&self, mut writer: &mut dyn std::io::Write) -> Result<()> {
+        return ShardedIndex::save(self, &mut writer);
+    }
+}
+
+impl<NbrT, DataT, DistanceFn> VectorIndex<DataT> for DurableIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    fn try_insert(&mut self, label: LabelT, data: &[DataT], ef_construction: usize) -> Result<()> {
+        return DurableIndex::try_insert(self, label, data, ef_construction);
+    }
+
+    fn try_query(
+        &self,
+        query: &[DataT],
+        ef_search: usize,
+        topk: usize,
+    ) -> Result<Vec<(LabelT, f32)>> {
+        return self.index().try_query(query, ef_search, topk);
+    }
+
+    fn remove(&mut self, label: LabelT) -> Result<bool> {
+        return self.try_remove(label);
+    }
+
+    fn len(&self) -> usize {
+        return self.index().len();
+    }
+
+    fn data_dim(&self) -> usize {
+        return self.index().data_dim();
+    }
+
+    fn save(&self, mut writer: &mut dyn std::io::Write) -> Result<()> {
+        return DurableIndex::save(self, &mut writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+    use crate::flatnav::error::Error;
+
+    fn check(index: &mut dyn VectorIndex<f32>) {
+        assert!(index.is_empty());
+        assert_eq!(index.try_query(&[0.0, 0.0], 16, 1), Err(Error::EmptyIndex));
+        assert!(index.try_insert(0, &[0.0], 16).is_err());
+
+        for i in 0..50 {
+            index.try_insert(i, &[i as f32, 0.0], 16).unwrap();
+        }
+        index.try_insert(7, &[7.5, 0.0], 16).unwrap();
+        assert_eq!(index.len(), 51);
+        assert_eq!(index.data_dim(), 2);
+
+        assert_eq!(index.try_query(&[7.1, 0.0], 16, 1).unwrap()[0].0, 7);
+        assert!(index.remove(7).unwrap());
+        assert!(!index.remove(7).unwrap());
+        assert_eq!(index.len(), 49);
+
+        let results = index.try_query(&[7.1, 0.0], 16, 2).unwrap();
+        let labels: Vec<u64> = results.iter().map(|x| x.0).collect();
+        assert_eq!(labels, vec![8, 6]);
+
+        let mut buf = Vec::new();
+        index.save(&mut buf).unwrap();
+        assert!(!buf.is_empty());
+    }
+
+    #[test]
+    fn test_vector_index() {
+        check(&mut FlatIndex::<f32, EuclideanDistance>::new(
+            2,
+            0,
+            EuclideanDistance,
+        ));
+
+        check(
+            &mut ShardedIndex::<u32, f32, EuclideanDistance>::new(3, 4, 2, EuclideanDistance)
+                .unwrap(),
+        );
+
+        let mut index = Index::<u32, f32, EuclideanDistance>::new(4, 2, 0, EuclideanDistance);
+        check(&mut index);
+        let mut buf = Vec::new();
+        VectorIndex::save(&index, &mut buf).unwrap();
+        let loaded =
+            Index::<u32, f32, EuclideanDistance>::load(&mut buf.as_slice(), EuclideanDistance)
+                .unwrap();
+        assert_eq!(loaded.len(), 49);
+        assert_eq!(loaded.query(&[7.1, 0.0], 16, 1)[0].0, 8);
+
+        // A saved DurableIndex opens as the snapshot of another directory.
+        let dir = std::env::temp_dir().join(format!("flatnav-vector-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut index = DurableIndex::<u32, f32, EuclideanDistance>::open(
+            dir.join("a"),
+            4,
+            2,
+            EuclideanDistance,
+        )
+        .unwrap();
+        check(&mut index);
+        let mut buf = Vec::new();
+        VectorIndex::save(&index, &mut buf).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("b").join("snapshot"), &buf).unwrap();
+        let opened = DurableIndex::<u32, f32, EuclideanDistance>::open(
+            dir.join("b"),
+            4,
+            2,
+            EuclideanDistance,
+        )
+        .unwrap();
+        assert_eq!(opened.index().len(), 49);
+        assert_eq!(opened.index().query(&[7.1, 0.0], 16, 1)[0].0, 8);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
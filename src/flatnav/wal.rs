@@ -0,0 +1,609 @@
+use super::checksum::crc32;
+use super::distance::Distance;
+use super::error::{Error, Result};
+use super::index::Index;
+use super::storage::{read_u32, read_u64, LabelT};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT: &str = "snapshot";
+const SNAPSHOT_TMP: &str = "snapshot.tmp";
+const WAL: &str = "wal";
+
+const OP_INSERT: u8 = 1;
+const OP_REMOVE: u8 = 2;
+const OP_UPSERT: u8 = 3;
+
+// Upper bound on the size of a log record, so that a corrupted length cannot
+// cause a huge allocation during replay.
+const MAX_RECORD_SIZE: usize = 1 << 30;
+const HEADER_SIZE: usize = 12;
+
+// Index persisted in a directory as the last snapshot plus a write-ahead log of
+// the operations since. Every operation is appended to the log before it is
+// applied, and opening the directory replays the log on top of the snapshot.
+// checkpoint() writes a new snapshot and empties the log.
+//
+// Log records are [Length u32][CRC-32 of Length u32][CRC-32 of body u32]
+// followed by the body [Sequence u64][Op u8][Label u64], and for inserts and
+// upserts [Namespace u32][ef_construction u64][data_dim u64][Data]
+// [Payload length u64][Payload].
+// An incomplete or corrupt last record, which is what a crash in the middle of
+// an append leaves behind, is dropped and the log is truncated there, while a
+// corrupt record followed by others fails the open. The length has its own
+// checksum so that a corrupted length is not mistaken for a record running
+// past the end of the log.
+// The snapshot starts with the sequence number of the last operation it
+// contains, so records that were already checkpointed are skipped if a crash
+// happened before the log was emptied. Data is written in native byte order,
+// as in the snapshot.
+pub struct DurableIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    index: Index<NbrT, DataT, DistanceFn>,
+    dir: PathBuf,
+    wal: File,
+    wal_len: u64,
+    // Sequence number of the last logged operation.
+    seq: u64,
+    fsync: bool,
+}
+
+struct Record<DataT> {
+    seq: u64,
+    op: u8,
+    label: LabelT,
+    namespace: u32,
+    ef_construction: usize,
+    data: Vec<DataT>,
+    payload: Vec<u8>,
+}
+
+impl<NbrT, DataT, DistanceFn> DurableIndex<NbrT, DataT, DistanceFn>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    // Opens the index in dir, creating an empty one with the given parameters
+    // if the directory has no snapshot yet.
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        max_nbrs: usize,
+        data_dim: usize,
+        distance_fn: DistanceFn,
+    ) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let snapshot_path = dir.join(SNAPSHOT);
+        let (mut index, snapshot_seq) = if snapshot_path.exists() {
+            let mut reader = BufReader::new(File::open(&snapshot_path)?);
+            let seq = read_u64(&mut reader)?;
+            (Index::load(&mut reader, distance_fn)?, seq)
+        } else {
+            (Index::new(max_nbrs, data_dim, 0, distance_fn), 0)
+        };
+        if index.data_dim() != data_dim {
+            return Err(Error::DimensionMismatch {
+                expected: data_dim,
+                got: index.data_dim(),
+            });
+        }
+        if index.max_nbrs() != max_nbrs {
+            return Err(Error::NeighborCountMismatch {
+                expected: max_nbrs,
+                got: index.max_nbrs(),
+            });
+        }
+
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(WAL))?;
+        let file_len = wal.metadata()?.len();
+        let mut seq = snapshot_seq;
+        let mut wal_len = 0;
+        {
+            let mut reader = BufReader::new(&wal);
+            while let Some((record, len)) = read_record(&mut reader, file_len - wal_len, data_dim)?
+            {
+                if record.seq > snapshot_seq {
+                    // Operations that failed when they were first applied were
+                    // reported then and fail the same way again.
+                    let _ = apply(&mut index, &record);
+                }
+                seq = seq.max(record.seq);
+                wal_len += len;
+            }
+        }
+        wal.set_len(wal_len)?;
+        wal.seek(SeekFrom::End(0))?;
+
+        return Ok(Self {
+            index,
+            dir,
+            wal,
+            wal_len,
+            seq,
+            fsync: false,
+        });
+    }
+
+    // By default records are handed to the OS as they are logged, which
+    // survives the process crashing but not the machine. With fsync enabled
+    // the log is also flushed to disk before every operation returns.
+    pub fn set_fsync(&mut self, fsync: bool) {
+        self.fsync = fsync;
+    }
+
+    // Flushes the log to disk.
+    pub fn sync(&self) -> Result<()> {
+        self.wal.sync_data()?;
+        return Ok(());
+    }
+
+    fn check_dim(&self, data: &[DataT]) -> Result<()> {
+        if data.len() != self.index.data_dim() {
+            return Err(Error::DimensionMismatch {
+                expected: self.index.data_dim(),
+                got: data.len(),
+            });
+        }
+        return Ok(());
+    }
+
+    fn log(&mut self, record: &Record<DataT>) -> Result<()> {
+        let body = encode(record);
+        let len = (body.len() as u32).to_le_bytes();
+        let mut buf = Vec::with_capacity(body.len() + HEADER_SIZE);
+        buf.extend_from_slice(&len);
+        buf.extend_from_slice(&crc32(&len).to_le_bytes());
+        buf.extend_from_slice(&crc32(&body).to_le_bytes());
+        buf.extend_from_slice(&body);
+
+        let written = self.wal.write_all(&buf).and_then(|_| {
+            if self.fsync {
+                self.wal.sync_data()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            // Drop a partially written record so that later records are not
+            // appended after it, where replay would never reach them.
+            let _ = self.wal.set_len(self.wal_len);
+            let _ = self.wal.seek(SeekFrom::Start(self.wal_len));
+            return Err(e.into());
+        }
+        self.wal_len += buf.len() as u64;
+        self.seq = record.seq;
+        return Ok(());
+    }
+
+    fn log_and_apply(&mut self, record: Record<DataT>) -> Result<()> {
+        self.log(&record)?;
+        return apply(&mut self.index, &record);
+    }
+
+    pub fn try_insert(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_insert_in_namespace(0, label, data, &[], ef_construction);
+    }
+
+    pub fn try_insert_in_namespace(
+        &mut self,
+        namespace: u32,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        ef_construction: usize,
+    ) -> Result<()> {
+        self.check_dim(data)?;
+        return self.log_and_apply(Record {
+            seq: self.seq + 1,
+            op: OP_INSERT,
+            label,
+            namespace,
+            ef_construction,
+            data: data.to_vec(),
+            payload: payload.to_vec(),
+        });
+    }
+
+    // Replaces all vectors with the label by the new one.
+    pub fn try_upsert(
+        &mut self,
+        label: LabelT,
+        data: &[DataT],
+        ef_construction: usize,
+    ) -> Result<()> {
+        return self.try_upsert_in_namespace(0, label, data, &[], ef_construction);
+    }
+
+    pub fn try_upsert_in_namespace(
+        &mut self,
+        namespace: u32,
+        label: LabelT,
+        data: &[DataT],
+        payload: &[u8],
+        ef_construction: usize,
+    ) -> Result<()> {
+        self.check_dim(data)?;
+        return self.log_and_apply(Record {
+            seq: self.seq + 1,
+            op: OP_UPSERT,
+            label,
+            namespace,
+            ef_construction,
+            data: data.to_vec(),
+            payload: payload.to_vec(),
+        });
+    }
+
+    // Removes all vectors with the label and returns whether there were any.
+    pub fn try_remove(&mut self, label: LabelT) -> Result<bool> {
+        self.log(&Record {
+            seq: self.seq + 1,
+            op: OP_REMOVE,
+            label,
+            namespace: 0,
+            ef_construction: 0,
+            data: Vec::new(),
+            payload: Vec::new(),
+        })?;
+        return Ok(self.index.remove(label));
+    }
+
+    // Writes the index in the snapshot format, so that the output can be put
+    // in a directory as its snapshot and opened there. It does not include the
+    // operations still in the log since the last checkpoint.
+    pub fn save<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.seq.to_le_bytes())?;
+        return self.index.save(writer);
+    }
+
+    // Writes a new snapshot and empties the log. The snapshot is written to a
+    // temporary file and renamed, so a crash leaves either the old or the new
+    // snapshot in place.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.save(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, self.dir.join(SNAPSHOT))?;
+        #[cfg(unix)]
+        File::open(&self.dir)?.sync_all()?;
+
+        self.wal.set_len(0)?;
+        self.wal.seek(SeekFrom::Start(0))?;
+        self.wal.sync_all()?;
+        self.wal_len = 0;
+        return Ok(());
+    }
+
+    pub fn index(&self) -> &Index<NbrT, DataT, DistanceFn> {
+        return &self.index;
+    }
+
+    // Size of the log in bytes.
+    pub fn wal_len(&self) -> u64 {
+        return self.wal_len;
+    }
+}
+
+fn apply<NbrT, DataT, DistanceFn>(
+    index: &mut Index<NbrT, DataT, DistanceFn>,
+    record: &Record<DataT>,
+) -> Result<()>
+where
+    NbrT: num_traits::PrimInt
+        + num_traits::AsPrimitive<usize>
+        + num_traits::FromPrimitive
+        + std::hash::Hash
+        + num_traits::Bounded,
+    DataT: num_traits::Num + Copy,
+    DistanceFn: Distance<DataT>,
+{
+    match record.op {
+        OP_REMOVE => {
+            index.remove(record.label);
+            Ok(())
+        }
+        OP_UPSERT => {
+            // The new vector is inserted before the old ones are removed, so
+            // that a failed insert leaves them in place.
+            let n_nodes = index.storage().len();
+            index.try_insert_in_namespace(
+                record.namespace,
+                record.label,
+                &record.data,
+                &record.payload,
+                record.ef_construction,
+            )?;
+            index.remove_before(record.label, n_nodes);
+            Ok(())
+        }
+        _ => index.try_insert_in_namespace(
+            record.namespace,
+            record.label,
+            &record.data,
+            &record.payload,
+            record.ef_construction,
+        ),
+    }
+}
+
+fn encode<DataT: Copy>(record: &Record<DataT>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&record.seq.to_le_bytes());
+    buf.push(record.op);
+    buf.extend_from_slice(&record.label.to_le_bytes());
+    if record.op != OP_REMOVE {
+        buf.extend_from_slice(&record.namespace.to_le_bytes());
+        buf.extend_from_slice(&(record.ef_construction as u64).to_le_bytes());
+        buf.extend_from_slice(&(record.data.len() as u64).to_le_bytes());
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                record.data.as_ptr() as *const u8,
+                std::mem::size_of_val(record.data.as_slice()),
+            )
+        };
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(&(record.payload.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&record.payload);
+    }
+    return buf;
+}
+
+fn decode<DataT: Copy>(mut body: &[u8], data_dim: usize) -> Result<Record<DataT>> {
+    let reader = &mut body;
+    let seq = read_u64(reader)?;
+    let mut op = [0];
+    reader.read_exact(&mut op)?;
+    let op = op[0];
+    let label = read_u64(reader)?;
+    let mut record = Record {
+        seq,
+        op,
+        label,
+        namespace: 0,
+        ef_construction: 0,
+        data: Vec::new(),
+        payload: Vec::new(),
+    };
+    match op {
+        OP_REMOVE => return Ok(record),
+        OP_INSERT | OP_UPSERT => {}
+        _ => return Err(Error::InvalidFormat(format!("unknown log op {}", op))),
+    }
+
+    record.namespace = read_u32(reader)?;
+    record.ef_construction = read_u64(reader)? as usize;
+    let n = read_u64(reader)? as usize;
+    if n != data_dim {
+        return Err(Error::DimensionMismatch {
+            expected: data_dim,
+            got: n,
+        });
+    }
+    let mut data = vec![0; n * std::mem::size_of::<DataT>()];
+    reader.read_exact(&mut data)?;
+    record.data = (0..n)
+        .map(|i| unsafe { std::ptr::read_unaligned((data.as_ptr() as *const DataT).add(i)) })
+        .collect();
+    let payload_len = read_u64(reader)? as usize;
+    if payload_len != reader.len() {
+        return Err(Error::InvalidFormat(
+            "invalid log record length".to_string(),
+        ));
+    }
+    record.payload = reader.to_vec();
+    return Ok(record);
+}
+
+// Returns the next record and its size in the log, or None at the end of the
+// valid part of the log. remaining is the number of bytes left in the log. A
+// record that is cut short or fails a checksum is a torn append only if it is
+// the last one, anything after it means the log is corrupt.
+fn read_record<R: Read, DataT: Copy>(
+    reader: &mut R,
+    remaining: u64,
+    data_dim: usize,
+) -> Result<Option<(Record<DataT>, u64)>> {
+    if remaining < HEADER_SIZE as u64 {
+        return Ok(None);
+    }
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let len_crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    if crc32(&header[..4]) != len_crc {
+        if remaining == HEADER_SIZE as u64 {
+            return Ok(None);
+        }
+        return Err(Error::InvalidFormat(
+            "checksum mismatch in log record length".to_string(),
+        ));
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[8..].try_into().unwrap());
+    if len > MAX_RECORD_SIZE {
+        return Err(Error::InvalidFormat(format!(
+            "log record of {} bytes is too large",
+            len
+        )));
+    }
+    // The length is known to be intact, so a body running past the end of the
+    // log was cut short by a crash.
+    let record_len = (len + HEADER_SIZE) as u64;
+    if record_len > remaining {
+        return Ok(None);
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    if crc32(&body) != crc {
+        if record_len == remaining {
+            return Ok(None);
+        }
+        return Err(Error::InvalidFormat(
+            "checksum mismatch in log record".to_string(),
+        ));
+    }
+    return match decode(&body, data_dim) {
+        Ok(record) => Ok(Some((record, record_len))),
+        Err(Error::Io(e)) => Err(Error::InvalidFormat(format!("invalid log record: {}", e))),
+        Err(e) => Err(e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flatnav::distance::EuclideanDistance;
+
+    type Durable = DurableIndex<u32, f32, EuclideanDistance>;
+
+    fn vec_of(i: usize) -> Vec<f32> {
+        return vec![i as f32, (i % 7) as f32];
+    }
+
+    fn labels(index: &Durable, i: usize) -> Vec<LabelT> {
+        return index
+            .index()
+            .query(&vec_of(i), 20, 3)
+            .iter()
+            .map(|&(label, _)| label)
+            .collect();
+    }
+
+    #[test]
+    fn test_durable_index() {
+        let dir = std::env::temp_dir().join(format!("flatnav-wal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut index = Durable::open(&dir, 8, 2, EuclideanDistance).unwrap();
+        for i in 0..100 {
+            index.try_insert(i as LabelT, &vec_of(i), 20).unwrap();
+        }
+        assert!(matches!(
+            index.try_insert(100, &[1.0], 20),
+            Err(Error::DimensionMismatch { .. })
+        ));
+        assert!(index.try_remove(10).unwrap());
+        assert!(!index.try_remove(1000).unwrap());
+        index
+            .try_upsert_in_namespace(2, 20, &vec_of(50), b"moved", 20)
+            .unwrap();
+        drop(index);
+
+        // Everything is replayed from the log.
+        let mut index = Durable::open(&dir, 8, 2, EuclideanDistance).unwrap();
+        assert_eq!(index.index().len(), 99);
+        assert!(!labels(&index, 10).contains(&10));
+        assert_eq!(index.index().namespace_len(2), 1);
+        assert!(labels(&index, 50).contains(&20));
+        assert!(!labels(&index, 20).contains(&20));
+
+        index.checkpoint().unwrap();
+        assert_eq!(index.wal_len(), 0);
+        index.try_insert(200, &vec_of(200), 20).unwrap();
+        let wal = std::fs::read(dir.join(WAL)).unwrap();
+        drop(index);
+
+        // A torn record at the end of the log is dropped.
+        let mut torn = wal.clone();
+        torn.extend_from_slice(&wal[..wal.len() - 3]);
+        std::fs::write(dir.join(WAL), &torn).unwrap();
+        let mut index = Durable::open(&dir, 8, 2, EuclideanDistance).unwrap();
+        assert_eq!(index.index().len(), 100);
+        assert_eq!(index.wal_len(), wal.len() as u64);
+        index.try_insert(201, &vec_of(201), 20).unwrap();
+        drop(index);
+        let index = Durable::open(&dir, 8, 2, EuclideanDistance).unwrap();
+        assert_eq!(index.index().len(), 101);
+        assert_eq!(labels(&index, 201)[0], 201);
+
+        // Records already in the snapshot are not applied twice.
+        let mut index = index;
+        index.checkpoint().unwrap();
+        drop(index);
+        std::fs::write(dir.join(WAL), &wal).unwrap();
+        let index = Durable::open(&dir, 8, 2, EuclideanDistance).unwrap();
+        assert_eq!(index.index().len(), 101);
+        drop(index);
+
+        // A complete last record that fails its checksum, but a corrupt
+        // record followed by others is an error.
+        let mut corrupt = wal.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        std::fs::write(dir.join(WAL), &corrupt).unwrap();
+        let index = Durable::open(&dir, 8, 2, EuclideanDistance).unwrap();
+        assert_eq!(index.index().len(), 101);
+        assert_eq!(index.wal_len(), 0);
+        drop(index);
+        corrupt.extend_from_slice(&wal);
+        std::fs::write(dir.join(WAL), &corrupt).unwrap();
+        assert!(matches!(
+            Durable::open(&dir, 8, 2, EuclideanDistance),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        // A corrupted length in the middle of the log fails the open instead of
+        // being taken for a torn tail that would truncate the later records.
+        let mut corrupt = wal.repeat(3);
+        corrupt[wal.len() + 2] ^= 0x40;
+        std::fs::write(dir.join(WAL), &corrupt).unwrap();
+        assert!(matches!(
+            Durable::open(&dir, 8, 2, EuclideanDistance),
+            Err(Error::InvalidFormat(_))
+        ));
+        assert_eq!(std::fs::read(dir.join(WAL)).unwrap(), corrupt);
+
+        assert!(matches!(
+            Durable::open(&dir, 8, 3, EuclideanDistance),
+            Err(Error::DimensionMismatch { .. })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_durable_index_failed_upsert() {
+        let dir = std::env::temp_dir().join(format!("flatnav-wal-full-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut index =
+            DurableIndex::<u8, f32, EuclideanDistance>::open(&dir, 4, 2, EuclideanDistance)
+                .unwrap();
+        let mut i = 0;
+        while index.try_insert(i as LabelT, &vec_of(i), 20).is_ok() {
+            i += 1;
+        }
+        let len = index.index().len();
+        assert!(matches!(
+            index.try_upsert(3, &vec_of(500), 20),
+            Err(Error::CapacityOverflow { .. })
+        ));
+        assert_eq!(index.index().len(), len);
+        assert_eq!(index.index().query(&vec_of(3), 20, 1)[0].0, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use flatnav::io::{BvecsReader, FvecsReader, IvecsReader, NpyReader};
+use flatnav::reordering::{
+    BfsOrder, DegreeSort, GOrder, HubCluster, RandomOrder, ReverseCuthillMckee,
+};
+use flatnav::{EuclideanDistance, IndexEuclideanF32, Reordering};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::process::ExitCode;
+use std::time::Instant;
+
+const USAGE: &str = "usage:
+  flatnav build --input <vectors.fvecs|.bvecs|.npy> --output <index> [--max-nbrs 32]
+                [--ef-construction 64] [--reorder none|gorder|rcm|bfs|degree|hub|random]
+                [--window 5] [--seed 0]
+  flatnav query --index <index> --queries <queries.fvecs|.bvecs|.npy> --output <results.txt>
+                [--ef-search 64] [--topk 10]
+  flatnav bench --index <index> --queries <queries.fvecs|.bvecs|.npy> --groundtruth <gt.ivecs>
+                [--ef-search 16,32,64,128] [--topk 10]";
+
+const BUILD_KEYS: &[&str] = &[
+    "input",
+    "output",
+    "max-nbrs",
+    "ef-construction",
+    "reorder",
+    "window",
+    "seed",
+];
+const QUERY_KEYS: &[&str] = &["index", "queries", "output", "ef-search", "topk"];
+const BENCH_KEYS: &[&str] = &["index", "queries", "groundtruth", "ef-search", "topk"];
+
+type CliResult<T> = Result<T, String>;
+
+struct Args {
+    values: HashMap<String, String>,
+}
+
+impl Args {
+    // Only the keys of the subcommand are accepted, so that a misspelled
+    // option is reported instead of silently falling back to its default.
+    fn parse(args: &[String], keys: &[&str]) -> CliResult<Self> {
+        let mut values = HashMap::new();
+        let mut iter = args.iter();
+        while let Some(key) = iter.next() {
+            let key = key
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument '{}'", key))?;
+            if !keys.contains(&key) {
+                return Err(format!("unknown argument --{}\n{}", key, USAGE));
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| format!("missing value for --{}", key))?;
+            values.insert(key.to_string(), value.clone());
+        }
+        Ok(Self { values })
+    }
+
+    fn required(&self, key: &str) -> CliResult<&str> {
+        self.values
+            .get(key)
+            .map(|x| x.as_str())
+            .ok_or_else(|| format!("missing required argument --{}", key))
+    }
+
+    fn get<T: std::str::FromStr>(&self, key: &str, default: T) -> CliResult<T> {
+        match self.values.get(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("invalid value '{}' for --{}", value, key)),
+            None => Ok(default),
+        }
+    }
+
+    fn get_list(&self, key: &str, default: &[usize]) -> CliResult<Vec<usize>> {
+        match self.values.get(key) {
+            Some(value) => value
+                .split(',')
+                .map(|x| {
+                    x.trim()
+                        .parse()
+                        .map_err(|_| format!("invalid value '{}' for --{}", x, key))
+                })
+                .collect(),
+            None => Ok(default.to_vec()),
+        }
+    }
+}
+
+type Vectors = Box<dyn Iterator<Item = flatnav::Result<Vec<f32>>>>;
+
+// Streams f32 vectors from .fvecs, .bvecs or .npy files based on the extension.
+fn open_vectors(path: &str) -> CliResult<Vectors> {
+    let err = |e: flatnav::Error| format!("cannot open {}: {}", path, e);
+    let vectors: Vectors = if path.ends_with(".fvecs") {
+        Box::new(FvecsReader::open(path).map_err(err)?)
+    } else if path.ends_with(".bvecs") {
+        Box::new(
+            BvecsReader::open(path)
+                .map_err(err)?
+                .map(|vec| Ok(vec?.into_iter().map(f32::from).collect())),
+        )
+    } else if path.ends_with(".npy") {
+        Box::new(NpyReader::<_, f32>::open(path).map_err(err)?)
+    } else {
+        return Err(format!("{}: expected a .fvecs, .bvecs or .npy file", path));
+    };
+    Ok(vectors)
+}
+
+fn read_vectors(path: &str) -> CliResult<Vec<Vec<f32>>> {
+    open_vectors(path)?
+        .collect::<flatnav::Result<_>>()
+        .map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+fn read_groundtruth(path: &str) -> CliResult<Vec<Vec<i32>>> {
+    IvecsReader::open(path)
+        .and_then(|reader| reader.collect())
+        .map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+fn load_index(path: &str) -> CliResult<IndexEuclideanF32> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    IndexEuclideanF32::load(&mut BufReader::new(file), EuclideanDistance)
+        .map_err(|e| format!("cannot load {}: {}", path, e))
+}
+
+fn reordering(args: &Args) -> CliResult<Option<Box<dyn Reordering>>> {
+    let window = args.get("window", 5)?;
+    let seed = args.get("seed", 0)?;
+    let reordering: Box<dyn Reordering> = match args.get("reorder", "none".to_string())?.as_str() {
+        "none" => return Ok(None),
+        "gorder" => Box::new(GOrder::new(window)),
+        "rcm" => Box::new(ReverseCuthillMckee::new()),
+        "bfs" => Box::new(BfsOrder::new(0)),
+        "degree" => Box::new(DegreeSort::new()),
+        "hub" => Box::new(HubCluster::new()),
+        "random" => Box::new(RandomOrder::new(seed)),
+        other => return Err(format!("unknown reordering '{}'", other)),
+    };
+    Ok(Some(reordering))
+}
+
+fn build(args: &Args) -> CliResult<()> {
+    let input = args.required("input")?;
+    let output = args.required("output")?;
+    let max_nbrs = args.get("max-nbrs", 32)?;
+    let ef_construction = args.get("ef-construction", 64)?;
+    let reordering = reordering(args)?;
+
+    let mut vectors = open_vectors(input)?.peekable();
+    let data_dim = match vectors.peek() {
+        Some(Ok(vector)) => vector.len(),
+        Some(Err(e)) => return Err(format!("cannot read {}: {}", input, e)),
+        None => return Err(format!("{} contains no vectors", input)),
+    };
+
+    let mut index = IndexEuclideanF32::new(max_nbrs, data_dim, 0, EuclideanDistance);
+
+    let start = Instant::now();
+    for (label, vector) in vectors.enumerate() {
+        let vector = vector.map_err(|e| format!("cannot read {}: {}", input, e))?;
+        index
+            .try_insert(label as u64, &vector, ef_construction)
+            .map_err(|e| format!("vector {}: {}", label, e))?;
+    }
+    eprintln!(
+        "built index of {} vectors of dim {} in {:.2?}",
+        index.len(),
+        data_dim,
+        start.elapsed()
+    );
+
+    if let Some(reordering) = reordering {
+        let start = Instant::now();
+        index.try_reorder(&*reordering).map_err(|e| e.to_string())?;
+        eprintln!("reordered index in {:.2?}", start.elapsed());
+    }
+
+    let file = File::create(output).map_err(|e| format!("cannot create {}: {}", output, e))?;
+    let mut writer = BufWriter::new(file);
+    index
+        .save(&mut writer)
+        .map_err(|e| format!("cannot save {}: {}", output, e))?;
+    writer
+        .flush()
+        .map_err(|e| format!("cannot save {}: {}", output, e))?;
+
+    Ok(())
+}
+
+fn query(args: &Args) -> CliResult<()> {
+    let index = load_index(args.required("index")?)?;
+    let queries = open_vectors(args.required("queries")?)?;
+    let output = args.required("output")?;
+    let ef_search = args.get("ef-search", 64)?;
+    let topk = args.get("topk", 10)?;
+
+    let file = File::create(output).map_err(|e| format!("cannot create {}: {}", output, e))?;
+    let mut writer = BufWriter::new(file);
+
+    // One line per query with space separated label:distance pairs.
+    for (i, query) in queries.enumerate() {
+        let query = query.map_err(|e| format!("query {}: {}", i, e))?;
+        let results = index
+            .try_query(&query, ef_search, topk)
+            .map_err(|e| format!("query {}: {}", i, e))?;
+        let line: Vec<String> = results
+            .iter()
+            .map(|(label, dist)| format!("{}:{}", label, dist))
+            .collect();
+        writeln!(writer, "{}", line.join(" "))
+            .map_err(|e| format!("cannot write {}: {}", output, e))?;
+    }
+    writer
+        .flush()
+        .map_err(|e| format!("cannot write {}: {}", output, e))?;
+
+    Ok(())
+}
+
+fn bench(args: &Args) -> CliResult<()> {
+    let index = load_index(args.required("index")?)?;
+    let queries = read_vectors(args.required("queries")?)?;
+    let groundtruth = read_groundtruth(args.required("groundtruth")?)?;
+    let ef_search = args.get_list("ef-search", &[16, 32, 64, 128])?;
+    let topk = args.get("topk", 10)?;
+
+    if groundtruth.len() < queries.len() {
+        return Err(format!(
+            "groundtruth has {} entries but there are {} queries",
+            groundtruth.len(),
+            queries.len()
+        ));
+    }
+
+    println!("ef_search\trecall@{}\tqps", topk);
+    for ef in ef_search {
+        let mut hits = 0;
+        let mut total = 0;
+
+        let start = Instant::now();
+        let results = queries
+            .iter()
+            .map(|query| index.try_query(query, ef, topk))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        let elapsed = start.elapsed();
+
+        for (results, gtruth) in results.iter().zip(groundtruth.iter()) {
+            let gtruth: HashSet<u64> = gtruth.iter().take(topk).map(|&x| x as u64).collect();
+            hits += results
+                .iter()
+                .filter(|(label, _)| gtruth.contains(label))
+                .count();
+            total += gtruth.len();
+        }
+
+        println!(
+            "{}\t{:.4}\t{:.1}",
+            ef,
+            hits as f64 / total.max(1) as f64,
+            queries.len() as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.split_first() {
+        Some((command, rest)) => match command.as_str() {
+            "build" => Args::parse(rest, BUILD_KEYS).and_then(|args| build(&args)),
+            "query" => Args::parse(rest, QUERY_KEYS).and_then(|args| query(&args)),
+            "bench" => Args::parse(rest, BENCH_KEYS).and_then(|args| bench(&args)),
+            _ => Err(format!("unknown command '{}'\n{}", command, USAGE)),
+        },
+        None => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
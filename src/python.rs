@@ -0,0 +1,1024 @@
+use crate::flatnav;
+use numpy::PyReadonlyArray1;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use pyo3::{exceptions, prelude::*};
+use pyo3::{pymodule, types::PyModule, Bound, PyResult};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+impl From<flatnav::Error> for PyErr {
+    fn from(err: flatnav::Error) -> PyErr {
+        let msg = err.to_string();
+        match err {
+            flatnav::Error::DimensionMismatch { .. }
+            | flatnav::Error::NeighborCountMismatch { .. }
+            | flatnav::Error::InvalidPermutation(_)
+            | flatnav::Error::InvalidGraph(_)
+            | flatnav::Error::InvalidFormat(_)
+            | flatnav::Error::InvalidAttributes(_)
+            | flatnav::Error::InvalidArgument(_) => PyErr::new::<exceptions::PyValueError, _>(msg),
+            flatnav::Error::CapacityOverflow { .. } | flatnav::Error::CapacityExceeded(_) => {
+                PyErr::new::<exceptions::PyOverflowError, _>(msg)
+            }
+            flatnav::Error::EmptyIndex => PyErr::new::<exceptions::PyIndexError, _>(msg),
+            flatnav::Error::Io(_) => PyErr::new::<exceptions::PyIOError, _>(msg),
+        }
+    }
+}
+
+// Queries on an empty index return no results, as they did before the
+// library reported EmptyIndex.
+fn or_empty<T>(results: flatnav::Result<Vec<T>>) -> PyResult<Vec<T>> {
+    return match results {
+        Err(flatnav::Error::EmptyIndex) => Ok(Vec::new()),
+        results => Ok(results?),
+    };
+}
+
+#[pyclass(subclass)]
+struct Reordering(Box<dyn flatnav::Reordering + Send + Sync>);
+
+#[pyclass(extends = Reordering, subclass)]
+struct GOrder {}
+
+#[pymethods]
+impl GOrder {
+    #[new]
+    fn new(w: usize) -> (Self, Reordering) {
+        (
+            Self {},
+            Reordering(Box::new(flatnav::reordering::GOrder::new(w))),
+        )
+    }
+}
+
+#[pyclass(extends = Reordering, subclass)]
+struct ReverseCuthillMckee {}
+
+#[pymethods]
+impl ReverseCuthillMckee {
+    #[new]
+    fn new() -> (Self, Reordering) {
+        (
+            Self {},
+            Reordering(Box::new(flatnav::reordering::ReverseCuthillMckee::new())),
+        )
+    }
+}
+
+#[pyclass(extends = Reordering, subclass)]
+struct BfsOrder {}
+
+#[pymethods]
+impl BfsOrder {
+    #[new]
+    #[pyo3(signature = (root=0))]
+    fn new(root: usize) -> (Self, Reordering) {
+        (
+            Self {},
+            Reordering(Box::new(flatnav::reordering::BfsOrder::new(root))),
+        )
+    }
+}
+
+#[pyclass(extends = Reordering, subclass)]
+struct DegreeSort {}
+
+#[pymethods]
+impl DegreeSort {
+    #[new]
+    fn new() -> (Self, Reordering) {
+        (
+            Self {},
+            Reordering(Box::new(flatnav::reordering::DegreeSort::new())),
+        )
+    }
+}
+
+#[pyclass(extends = Reordering, subclass)]
+struct HubCluster {}
+
+#[pymethods]
+impl HubCluster {
+    #[new]
+    fn new() -> (Self, Reordering) {
+        (
+            Self {},
+            Reordering(Box::new(flatnav::reordering::HubCluster::new())),
+        )
+    }
+}
+
+#[pyclass(extends = Reordering, subclass)]
+struct RandomOrder {}
+
+#[pymethods]
+impl RandomOrder {
+    #[new]
+    #[pyo3(signature = (seed=0))]
+    fn new(seed: u64) -> (Self, Reordering) {
+        (
+            Self {},
+            Reordering(Box::new(flatnav::reordering::RandomOrder::new(seed))),
+        )
+    }
+}
+
+#[pyclass(get_all)]
+struct ReorderingMetrics {
+    avg_gap: f64,
+    avg_log_gap: f64,
+    bandwidth: usize,
+    gorder_score: u64,
+}
+
+#[pymethods]
+impl ReorderingMetrics {
+    fn __repr__(&self) -> String {
+        format!(
+            "ReorderingMetrics(avg_gap={}, avg_log_gap={}, bandwidth={}, gorder_score={})",
+            self.avg_gap, self.avg_log_gap, self.bandwidth, self.gorder_score
+        )
+    }
+}
+
+impl From<flatnav::reordering::metrics::Metrics> for ReorderingMetrics {
+    fn from(metrics: flatnav::reordering::metrics::Metrics) -> Self {
+        Self {
+            avg_gap: metrics.avg_gap,
+            avg_log_gap: metrics.avg_log_gap,
+            bandwidth: metrics.bandwidth,
+            gorder_score: metrics.gorder_score,
+        }
+    }
+}
+
+fn value(obj: &Bound<'_, PyAny>) -> PyResult<flatnav::Value> {
+    if let Ok(x) = obj.extract::<i64>() {
+        return Ok(flatnav::Value::Int(x));
+    }
+    if let Ok(tag) = obj.extract::<String>() {
+        return Ok(flatnav::Value::Tag(tag));
+    }
+    Err(PyErr::new::<exceptions::PyTypeError, _>(format!(
+        "attribute values must be int or str, got {}",
+        obj.get_type().name()?
+    )))
+}
+
+// Attributes are passed as a dict from name to an int, a str or a list of
+// them for multi valued attributes.
+fn attributes(dict: &Bound<'_, PyDict>) -> PyResult<flatnav::Attributes> {
+    let mut attributes = flatnav::Attributes::new();
+    for (name, values) in dict.iter() {
+        let name = name.extract::<String>()?;
+        match values.downcast::<PyList>() {
+            Ok(values) => {
+                for x in values.iter() {
+                    attributes.push(&name, value(&x)?);
+                }
+            }
+            Err(_) => attributes.push(&name, value(&values)?),
+        }
+    }
+    Ok(attributes)
+}
+
+fn payload(payload: Option<&[u8]>, attributes: Option<Bound<'_, PyDict>>) -> PyResult<Vec<u8>> {
+    match (payload, attributes) {
+        (Some(_), Some(_)) => Err(PyErr::new::<exceptions::PyValueError, _>(
+            "payload and attributes cannot both be set",
+        )),
+        (Some(payload), None) => Ok(payload.to_vec()),
+        (None, Some(dict)) => Ok(self::attributes(&dict)?.encode()?),
+        (None, None) => Ok(Vec::new()),
+    }
+}
+
+#[pyclass(get_all)]
+struct NamespaceStats {
+    len: usize,
+    edges: usize,
+    intra_edges: usize,
+}
+
+#[pymethods]
+impl NamespaceStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "NamespaceStats(len={}, edges={}, intra_edges={})",
+            self.len, self.edges, self.intra_edges
+        )
+    }
+}
+
+impl From<flatnav::NamespaceStats> for NamespaceStats {
+    fn from(stats: flatnav::NamespaceStats) -> Self {
+        Self {
+            len: stats.len,
+            edges: stats.edges,
+            intra_edges: stats.intra_edges,
+        }
+    }
+}
+
+#[pyclass(get_all)]
+struct GraphStats {
+    n_nodes: usize,
+    n_edges: usize,
+    out_degrees: Vec<usize>,
+    in_degrees: Vec<usize>,
+    n_components: usize,
+    n_unreachable: usize,
+    avg_edge_length: f64,
+}
+
+#[pymethods]
+impl GraphStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "GraphStats(n_nodes={}, n_edges={}, n_components={}, n_unreachable={}, avg_edge_length={})",
+            self.n_nodes, self.n_edges, self.n_components, self.n_unreachable, self.avg_edge_length
+        )
+    }
+}
+
+impl From<flatnav::GraphStats> for GraphStats {
+    fn from(stats: flatnav::GraphStats) -> Self {
+        Self {
+            n_nodes: stats.n_nodes,
+            n_edges: stats.n_edges,
+            out_degrees: stats.out_degrees,
+            in_degrees: stats.in_degrees,
+            n_components: stats.n_components,
+            n_unreachable: stats.n_unreachable,
+            avg_edge_length: stats.avg_edge_length,
+        }
+    }
+}
+
+#[pyclass(name = "Filter", frozen)]
+struct Filter(flatnav::Filter);
+
+#[pymethods]
+impl Filter {
+    #[staticmethod]
+    fn eq(name: &str, value: Bound<'_, PyAny>) -> PyResult<Self> {
+        Ok(Self(flatnav::Filter::Eq(
+            name.to_string(),
+            self::value(&value)?,
+        )))
+    }
+
+    #[staticmethod]
+    fn is_in(name: &str, values: Vec<Bound<'_, PyAny>>) -> PyResult<Self> {
+        let values = values.iter().map(value).collect::<PyResult<_>>()?;
+        Ok(Self(flatnav::Filter::In(name.to_string(), values)))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (name, min=i64::MIN, max=i64::MAX))]
+    fn range(name: &str, min: i64, max: i64) -> Self {
+        Self(flatnav::Filter::range(name, min, max))
+    }
+
+    fn __and__(&self, other: PyRef<'_, Filter>) -> Self {
+        Self(flatnav::Filter::And(vec![self.0.clone(), other.0.clone()]))
+    }
+
+    fn __or__(&self, other: PyRef<'_, Filter>) -> Self {
+        Self(flatnav::Filter::Or(vec![self.0.clone(), other.0.clone()]))
+    }
+
+    fn __invert__(&self) -> Self {
+        Self(flatnav::Filter::Not(Box::new(self.0.clone())))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Filter({:?})", self.0)
+    }
+}
+
+// Defines a Python class wrapping a flatnav::VectorIndex implementation. The
+// index sits behind a RwLock so that every method can take &self and run with
+// the GIL released: queries from different Python threads proceed in parallel
+// while inserts, reorders and state changes take the write lock. Methods which
+// only exist for some index kinds, including the constructor, are passed in.
+macro_rules! py_index {
+    ($name:ident, $rust:ty, $data:ty, $distance:expr, { $($methods:tt)* }) => {
+        #[pyclass(module = "flatnav")]
+        struct $name {
+            index: RwLock<$rust>,
+        }
+
+        impl $name {
+            fn read(&self) -> PyResult<RwLockReadGuard<'_, $rust>> {
+                check_not_filtering(self.addr())?;
+                self.index.read().map_err(|_| poisoned())
+            }
+
+            fn write(&self) -> PyResult<RwLockWriteGuard<'_, $rust>> {
+                check_not_filtering(self.addr())?;
+                self.index.write().map_err(|_| poisoned())
+            }
+
+            fn addr(&self) -> usize {
+                self as *const Self as usize
+            }
+        }
+
+        #[pymethods]
+        impl $name {
+            $($methods)*
+
+            #[pyo3(signature = (label, data, ef_construction, payload=None, attributes=None, namespace=0))]
+            #[allow(clippy::too_many_arguments)]
+            fn insert(
+                &self,
+                py: Python<'_>,
+                label: u64,
+                data: PyReadonlyArray1<$data>,
+                ef_construction: usize,
+                payload: Option<&[u8]>,
+                attributes: Option<Bound<'_, PyDict>>,
+                namespace: u32,
+            ) -> PyResult<()> {
+                // Copy the input so the array can be modified by other threads while
+                // the GIL is released.
+                let data = data.as_slice()?.to_vec();
+                let payload = self::payload(payload, attributes)?;
+                py.allow_threads(|| {
+                    self.write()?.try_insert_in_namespace(
+                        namespace,
+                        label,
+                        &data,
+                        &payload,
+                        ef_construction,
+                    )?;
+                    Ok(())
+                })
+            }
+
+            #[pyo3(signature = (query, ef_search, topk, namespace=None))]
+            fn query(
+                &self,
+                py: Python<'_>,
+                query: PyReadonlyArray1<$data>,
+                ef_search: usize,
+                topk: usize,
+                namespace: Option<u32>,
+            ) -> PyResult<Vec<(u64, f32)>> {
+                let query = query.as_slice()?.to_vec();
+                py.allow_threads(|| {
+                    let index = self.read()?;
+                    return or_empty(match namespace {
+                        Some(namespace) => {
+                            index.try_query_in_namespace(namespace, &query, ef_search, topk)
+                        }
+                        None => index.try_query(&query, ef_search, topk),
+                    });
+                })
+            }
+
+            fn query_with_payload<'py>(
+                &self,
+                py: Python<'py>,
+                query: PyReadonlyArray1<$data>,
+                ef_search: usize,
+                topk: usize,
+            ) -> PyResult<Vec<(u64, f32, Bound<'py, PyBytes>)>> {
+                let query = query.as_slice()?.to_vec();
+                let results = py.allow_threads(|| -> PyResult<Vec<(u64, f32, Vec<u8>)>> {
+                    let index = self.read()?;
+                    let results =
+                        or_empty(index.try_query_with_payload(&query, ef_search, topk))?;
+                    Ok(results
+                        .into_iter()
+                        .map(|(label, dist, payload)| (label, dist, payload.to_vec()))
+                        .collect())
+                })?;
+                Ok(results
+                    .into_iter()
+                    .map(|(label, dist, payload)| (label, dist, PyBytes::new(py, &payload)))
+                    .collect())
+            }
+
+            // The filter is a Python callable taking (label, payload) so the GIL is
+            // held for the whole search. The first exception raised by the filter
+            // stops it from matching anything further and is re-raised. The filter
+            // must not use the index itself, which raises RuntimeError.
+            fn query_filtered(
+                &self,
+                py: Python<'_>,
+                query: PyReadonlyArray1<$data>,
+                ef_search: usize,
+                topk: usize,
+                filter: Bound<'_, PyAny>,
+            ) -> PyResult<Vec<(u64, f32)>> {
+                let error = std::cell::RefCell::new(None);
+                let index = self.read()?;
+                let _guard = FilterGuard::new(self.addr());
+                let results = or_empty(index.try_query_filtered(
+                    query.as_slice()?,
+                    ef_search,
+                    topk,
+                    |label, payload| {
+                        if error.borrow().is_some() {
+                            return false;
+                        }
+                        match filter
+                            .call1((label, PyBytes::new(py, payload)))
+                            .and_then(|x| x.is_truthy())
+                        {
+                            Ok(keep) => keep,
+                            Err(e) => {
+                                *error.borrow_mut() = Some(e);
+                                false
+                            }
+                        }
+                    },
+                ))?;
+                match error.into_inner() {
+                    Some(e) => Err(e),
+                    None => Ok(results),
+                }
+            }
+
+            fn query_with_filter(
+                &self,
+                py: Python<'_>,
+                query: PyReadonlyArray1<$data>,
+                ef_search: usize,
+                topk: usize,
+                filter: PyRef<'_, Filter>,
+            ) -> PyResult<Vec<(u64, f32)>> {
+                let query = query.as_slice()?.to_vec();
+                let filter = &filter.0;
+                py.allow_threads(|| {
+                    return or_empty(
+                        self.read()?
+                            .try_query_with_filter(&query, ef_search, topk, filter),
+                    );
+                })
+            }
+
+            fn remove(&self, py: Python<'_>, label: u64) -> PyResult<bool> {
+                py.allow_threads(|| Ok(flatnav::VectorIndex::remove(&mut *self.write()?, label)?))
+            }
+
+            fn save(&self, py: Python<'_>, path: std::path::PathBuf) -> PyResult<()> {
+                py.allow_threads(|| {
+                    let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+                    self.read()?.save(&mut writer)?;
+                    std::io::Write::flush(&mut writer)?;
+                    Ok(())
+                })
+            }
+
+            #[staticmethod]
+            fn load(py: Python<'_>, path: std::path::PathBuf) -> PyResult<Self> {
+                py.allow_threads(|| {
+                    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+                    let index = <$rust>::load(&mut reader, $distance)?;
+                    Ok(Self {
+                        index: RwLock::new(index),
+                    })
+                })
+            }
+
+            fn __len__(&self) -> PyResult<usize> {
+                Ok(flatnav::VectorIndex::len(&*self.read()?))
+            }
+
+            fn __getstate__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+                let state = py.allow_threads(|| -> PyResult<Vec<u8>> {
+                    let mut state = Vec::new();
+                    self.read()?.save(&mut state)?;
+                    Ok(state)
+                })?;
+                Ok(PyBytes::new(py, &state))
+            }
+
+            fn __setstate__(&self, py: Python<'_>, state: &[u8]) -> PyResult<()> {
+                py.allow_threads(|| {
+                    let index =
+                        <$rust>::load(&mut &state[..], $distance)?;
+                    *self.write()? = index;
+                    Ok(())
+                })
+            }
+
+            fn __copy__(&self, py: Python<'_>) -> PyResult<Self> {
+                py.allow_threads(|| {
+                    Ok(Self {
+                        index: RwLock::new(self.read()?.clone()),
+                    })
+                })
+            }
+
+            fn __deepcopy__(&self, py: Python<'_>, _memo: &Bound<'_, PyAny>) -> PyResult<Self> {
+                self.__copy__(py)
+            }
+        }
+    };
+}
+
+fn poisoned() -> PyErr {
+    PyErr::new::<exceptions::PyRuntimeError, _>("index lock poisoned by a panic in another thread")
+}
+
+thread_local! {
+    // Addresses of the indexes whose query_filtered callback is running on
+    // this thread.
+    static FILTERING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+// Marks the index as running a filter callback on this thread until dropped.
+struct FilterGuard;
+
+impl FilterGuard {
+    fn new(index: usize) -> Self {
+        FILTERING.with_borrow_mut(|indexes| indexes.push(index));
+        FilterGuard
+    }
+}
+
+impl Drop for FilterGuard {
+    fn drop(&mut self) {
+        FILTERING.with_borrow_mut(|indexes| indexes.pop());
+    }
+}
+
+// The filter callback runs while query_filtered holds the read lock, so using
+// the same index from the callback would deadlock once it waits for the write
+// lock, or for the read lock behind a waiting writer. Raise instead.
+fn check_not_filtering(index: usize) -> PyResult<()> {
+    if FILTERING.with_borrow(|indexes| indexes.contains(&index)) {
+        return Err(PyErr::new::<exceptions::PyRuntimeError, _>(
+            "the index cannot be used from within its own query_filtered callback",
+        ));
+    }
+    Ok(())
+}
+
+macro_rules! py_graph_index {
+    ($name:ident, $rust:ty, $data:ty, $distance:expr) => {
+        py_index!($name, $rust, $data, $distance, {
+            #[new]
+            fn new(max_nbrs: usize, data_dim: usize, capacity: usize) -> PyResult<Self> {
+                Ok(Self {
+                    index: RwLock::new(<$rust>::new(max_nbrs, data_dim, capacity, $distance)),
+                })
+            }
+
+            fn reorder(&self, py: Python<'_>, reordering: PyRef<'_, Reordering>) -> PyResult<()> {
+                let reordering = &*reordering.0;
+                py.allow_threads(|| {
+                    self.write()?.try_reorder(reordering)?;
+                    Ok(())
+                })
+            }
+
+            // perm may be an array of any integer dtype or a sequence of ints. Arrays
+            // of int64 are read directly and anything else is converted element by
+            // element.
+            fn apply_permutation(&self, py: Python<'_>, perm: Bound<'_, PyAny>) -> PyResult<()> {
+                let perm: Vec<i64> = match perm.extract::<PyReadonlyArray1<i64>>() {
+                    Ok(perm) => perm.as_array().to_vec(),
+                    Err(_) => perm.extract()?,
+                };
+                let perm = perm
+                    .into_iter()
+                    .map(|pos| {
+                        usize::try_from(pos).map_err(|_| {
+                            flatnav::Error::InvalidPermutation(format!("negative position {}", pos))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                py.allow_threads(|| {
+                    self.write()?.apply_permutation(&perm)?;
+                    Ok(())
+                })
+            }
+
+            // Links every appended node unless n_samples is given. The other index
+            // is copied first so that an index can be merged with itself.
+            #[pyo3(signature = (other, ef_construction, n_samples=None))]
+            fn merge(
+                &self,
+                py: Python<'_>,
+                other: PyRef<'_, Self>,
+                ef_construction: usize,
+                n_samples: Option<usize>,
+            ) -> PyResult<()> {
+                let other = &*other;
+                py.allow_threads(|| {
+                    let other = other.read()?.clone();
+                    self.write()?.merge(
+                        &other,
+                        ef_construction,
+                        n_samples.unwrap_or(usize::MAX),
+                    )?;
+                    Ok(())
+                })
+            }
+
+            fn namespace_stats(&self, py: Python<'_>) -> PyResult<BTreeMap<u32, NamespaceStats>> {
+                py.allow_threads(|| {
+                    let stats = self.read()?.namespace_stats();
+                    Ok(stats.into_iter().map(|(k, v)| (k, v.into())).collect())
+                })
+            }
+
+            fn graph_stats(&self, py: Python<'_>) -> PyResult<GraphStats> {
+                py.allow_threads(|| Ok(self.read()?.graph_stats().into()))
+            }
+
+            fn out_nodes(&self, py: Python<'_>) -> PyResult<Vec<Vec<usize>>> {
+                py.allow_threads(|| Ok(self.read()?.out_nodes()))
+            }
+
+            #[pyo3(signature = (w=5))]
+            fn reordering_metrics(&self, py: Python<'_>, w: usize) -> PyResult<ReorderingMetrics> {
+                py.allow_threads(|| Ok(self.read()?.reordering_metrics(w)?.into()))
+            }
+
+            fn __getnewargs__(&self) -> PyResult<(usize, usize, usize)> {
+                let index = self.read()?;
+                Ok((index.max_nbrs(), index.data_dim(), 0))
+            }
+        });
+    };
+}
+
+// Exact counterparts of the graph indexes with the same methods so the two can
+// be swapped, the ef_construction and ef_search arguments are ignored.
+macro_rules! py_flat_index {
+    ($name:ident, $rust:ty, $data:ty, $distance:expr) => {
+        py_index!($name, $rust, $data, $distance, {
+            #[new]
+            fn new(data_dim: usize, capacity: usize) -> PyResult<Self> {
+                Ok(Self {
+                    index: RwLock::new(<$rust>::new(data_dim, capacity, $distance)),
+                })
+            }
+
+            fn __getnewargs__(&self) -> PyResult<(usize, usize)> {
+                Ok((self.read()?.data_dim(), 0))
+            }
+        });
+    };
+}
+
+py_graph_index!(
+    IndexEuclideanF32,
+    flatnav::IndexEuclideanF32,
+    f32,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexEuclideanU8,
+    flatnav::IndexEuclideanU8,
+    u8,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexEuclideanI8,
+    flatnav::IndexEuclideanI8,
+    i8,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexInnerProductF32,
+    flatnav::IndexInnerProductF32,
+    f32,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexInnerProductU8,
+    flatnav::IndexInnerProductU8,
+    u8,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexInnerProductI8,
+    flatnav::IndexInnerProductI8,
+    i8,
+    flatnav::InnerProductDistance
+);
+
+py_graph_index!(
+    IndexEuclideanF32U16,
+    flatnav::IndexEuclideanF32U16,
+    f32,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexEuclideanU8U16,
+    flatnav::IndexEuclideanU8U16,
+    u8,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexEuclideanI8U16,
+    flatnav::IndexEuclideanI8U16,
+    i8,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexInnerProductF32U16,
+    flatnav::IndexInnerProductF32U16,
+    f32,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexInnerProductU8U16,
+    flatnav::IndexInnerProductU8U16,
+    u8,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexInnerProductI8U16,
+    flatnav::IndexInnerProductI8U16,
+    i8,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexEuclideanF32U64,
+    flatnav::IndexEuclideanF32U64,
+    f32,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexEuclideanU8U64,
+    flatnav::IndexEuclideanU8U64,
+    u8,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexEuclideanI8U64,
+    flatnav::IndexEuclideanI8U64,
+    i8,
+    flatnav::EuclideanDistance
+);
+py_graph_index!(
+    IndexInnerProductF32U64,
+    flatnav::IndexInnerProductF32U64,
+    f32,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexInnerProductU8U64,
+    flatnav::IndexInnerProductU8U64,
+    u8,
+    flatnav::InnerProductDistance
+);
+py_graph_index!(
+    IndexInnerProductI8U64,
+    flatnav::IndexInnerProductI8U64,
+    i8,
+    flatnav::InnerProductDistance
+);
+
+py_flat_index!(
+    FlatIndexEuclideanF32,
+    flatnav::FlatIndexEuclideanF32,
+    f32,
+    flatnav::EuclideanDistance
+);
+py_flat_index!(
+    FlatIndexEuclideanU8,
+    flatnav::FlatIndexEuclideanU8,
+    u8,
+    flatnav::EuclideanDistance
+);
+py_flat_index!(
+    FlatIndexEuclideanI8,
+    flatnav::FlatIndexEuclideanI8,
+    i8,
+    flatnav::EuclideanDistance
+);
+py_flat_index!(
+    FlatIndexInnerProductF32,
+    flatnav::FlatIndexInnerProductF32,
+    f32,
+    flatnav::InnerProductDistance
+);
+py_flat_index!(
+    FlatIndexInnerProductU8,
+    flatnav::FlatIndexInnerProductU8,
+    u8,
+    flatnav::InnerProductDistance
+);
+py_flat_index!(
+    FlatIndexInnerProductI8,
+    flatnav::FlatIndexInnerProductI8,
+    i8,
+    flatnav::InnerProductDistance
+);
+
+fn metric_name(metric: &str) -> PyResult<&'static str> {
+    match metric {
+        "l2" | "euclidean" => Ok("l2"),
+        "ip" | "inner_product" => Ok("ip"),
+        _ => Err(PyErr::new::<exceptions::PyValueError, _>(format!(
+            "unknown metric '{}', expected 'l2' or 'ip'",
+            metric
+        ))),
+    }
+}
+
+// Accepts dtype names as well as numpy scalar types and dtypes.
+fn dtype_name(dtype: &Bound<'_, PyAny>) -> PyResult<String> {
+    let name = match dtype.getattr("__name__") {
+        Ok(name) => name.extract::<String>()?,
+        Err(_) => dtype.str()?.extract::<String>()?,
+    };
+    match name.as_str() {
+        "float32" | "uint8" | "int8" => Ok(name),
+        _ => Err(PyErr::new::<exceptions::PyValueError, _>(format!(
+            "unsupported dtype '{}', expected float32, uint8 or int8",
+            name
+        ))),
+    }
+}
+
+fn new_object<T: pyo3::PyClass + Into<PyClassInitializer<T>>>(
+    py: Python<'_>,
+    index: PyResult<T>,
+) -> PyResult<PyObject> {
+    Ok(Py::new(py, index?)?.into_any())
+}
+
+// Without an explicit nbr_type node ids are uint32, or uint64 when capacity is
+// past what uint32 can address. Since capacity only preallocates and does not
+// bound the index, uint16 is never picked from it and has to be asked for.
+fn nbr_type_name(nbr_type: Option<&str>, capacity: usize) -> PyResult<&'static str> {
+    match nbr_type {
+        Some("uint16") => Ok("uint16"),
+        Some("uint32") => Ok("uint32"),
+        Some("uint64") => Ok("uint64"),
+        Some(nbr_type) => Err(PyErr::new::<exceptions::PyValueError, _>(format!(
+            "unsupported nbr_type '{}', expected uint16, uint32 or uint64",
+            nbr_type
+        ))),
+        None if capacity <= flatnav::IndexEuclideanF32::max_nodes() => Ok("uint32"),
+        None => Ok("uint64"),
+    }
+}
+
+#[pyfunction(name = "Index")]
+#[pyo3(signature = (max_nbrs, data_dim, capacity=0, metric="l2", dtype=None, nbr_type=None))]
+fn new_index(
+    py: Python<'_>,
+    max_nbrs: usize,
+    data_dim: usize,
+    capacity: usize,
+    metric: &str,
+    dtype: Option<Bound<'_, PyAny>>,
+    nbr_type: Option<&str>,
+) -> PyResult<PyObject> {
+    let metric = metric_name(metric)?;
+    let dtype = match dtype {
+        Some(dtype) => dtype_name(&dtype)?,
+        None => "float32".to_string(),
+    };
+    let nbr_type = nbr_type_name(nbr_type, capacity)?;
+    match (metric, dtype.as_str(), nbr_type) {
+        ("l2", "float32", "uint16") => {
+            new_object(py, IndexEuclideanF32U16::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "uint8", "uint16") => {
+            new_object(py, IndexEuclideanU8U16::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "int8", "uint16") => {
+            new_object(py, IndexEuclideanI8U16::new(max_nbrs, data_dim, capacity))
+        }
+        ("ip", "float32", "uint16") => new_object(
+            py,
+            IndexInnerProductF32U16::new(max_nbrs, data_dim, capacity),
+        ),
+        ("ip", "uint8", "uint16") => new_object(
+            py,
+            IndexInnerProductU8U16::new(max_nbrs, data_dim, capacity),
+        ),
+        ("ip", "int8", "uint16") => new_object(
+            py,
+            IndexInnerProductI8U16::new(max_nbrs, data_dim, capacity),
+        ),
+        ("l2", "float32", "uint32") => {
+            new_object(py, IndexEuclideanF32::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "uint8", "uint32") => {
+            new_object(py, IndexEuclideanU8::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "int8", "uint32") => {
+            new_object(py, IndexEuclideanI8::new(max_nbrs, data_dim, capacity))
+        }
+        ("ip", "float32", "uint32") => {
+            new_object(py, IndexInnerProductF32::new(max_nbrs, data_dim, capacity))
+        }
+        ("ip", "uint8", "uint32") => {
+            new_object(py, IndexInnerProductU8::new(max_nbrs, data_dim, capacity))
+        }
+        ("ip", "int8", "uint32") => {
+            new_object(py, IndexInnerProductI8::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "float32", "uint64") => {
+            new_object(py, IndexEuclideanF32U64::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "uint8", "uint64") => {
+            new_object(py, IndexEuclideanU8U64::new(max_nbrs, data_dim, capacity))
+        }
+        ("l2", "int8", "uint64") => {
+            new_object(py, IndexEuclideanI8U64::new(max_nbrs, data_dim, capacity))
+        }
+        ("ip", "float32", "uint64") => new_object(
+            py,
+            IndexInnerProductF32U64::new(max_nbrs, data_dim, capacity),
+        ),
+        ("ip", "uint8", "uint64") => new_object(
+            py,
+            IndexInnerProductU8U64::new(max_nbrs, data_dim, capacity),
+        ),
+        ("ip", "int8", "uint64") => new_object(
+            py,
+            IndexInnerProductI8U64::new(max_nbrs, data_dim, capacity),
+        ),
+        _ => unreachable!(),
+    }
+}
+
+#[pyfunction(name = "FlatIndex")]
+#[pyo3(signature = (data_dim, capacity=0, metric="l2", dtype=None))]
+fn new_flat_index(
+    py: Python<'_>,
+    data_dim: usize,
+    capacity: usize,
+    metric: &str,
+    dtype: Option<Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    let metric = metric_name(metric)?;
+    let dtype = match dtype {
+        Some(dtype) => dtype_name(&dtype)?,
+        None => "float32".to_string(),
+    };
+    match (metric, dtype.as_str()) {
+        ("l2", "float32") => new_object(py, FlatIndexEuclideanF32::new(data_dim, capacity)),
+        ("l2", "uint8") => new_object(py, FlatIndexEuclideanU8::new(data_dim, capacity)),
+        ("l2", "int8") => new_object(py, FlatIndexEuclideanI8::new(data_dim, capacity)),
+        ("ip", "float32") => new_object(py, FlatIndexInnerProductF32::new(data_dim, capacity)),
+        ("ip", "uint8") => new_object(py, FlatIndexInnerProductU8::new(data_dim, capacity)),
+        ("ip", "int8") => new_object(py, FlatIndexInnerProductI8::new(data_dim, capacity)),
+        _ => unreachable!(),
+    }
+}
+
+#[pymodule(name = "flatnav")]
+fn flatnav_lib(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Reordering>()?;
+    m.add_class::<GOrder>()?;
+    m.add_class::<ReverseCuthillMckee>()?;
+    m.add_class::<BfsOrder>()?;
+    m.add_class::<DegreeSort>()?;
+    m.add_class::<HubCluster>()?;
+    m.add_class::<RandomOrder>()?;
+
+    m.add_class::<ReorderingMetrics>()?;
+
+    m.add_class::<Filter>()?;
+    m.add_class::<NamespaceStats>()?;
+    m.add_class::<GraphStats>()?;
+
+    m.add_class::<IndexEuclideanF32>()?;
+    m.add_class::<IndexEuclideanU8>()?;
+    m.add_class::<IndexEuclideanI8>()?;
+    m.add_class::<IndexInnerProductF32>()?;
+    m.add_class::<IndexInnerProductU8>()?;
+    m.add_class::<IndexInnerProductI8>()?;
+    m.add_class::<IndexEuclideanF32U16>()?;
+    m.add_class::<IndexEuclideanU8U16>()?;
+    m.add_class::<IndexEuclideanI8U16>()?;
+    m.add_class::<IndexInnerProductF32U16>()?;
+    m.add_class::<IndexInnerProductU8U16>()?;
+    m.add_class::<IndexInnerProductI8U16>()?;
+    m.add_class::<IndexEuclideanF32U64>()?;
+    m.add_class::<IndexEuclideanU8U64>()?;
+    m.add_class::<IndexEuclideanI8U64>()?;
+    m.add_class::<IndexInnerProductF32U64>()?;
+    m.add_class::<IndexInnerProductU8U64>()?;
+    m.add_class::<IndexInnerProductI8U64>()?;
+
+    m.add_class::<FlatIndexEuclideanF32>()?;
+    m.add_class::<FlatIndexEuclideanU8>()?;
+    m.add_class::<FlatIndexEuclideanI8>()?;
+    m.add_class::<FlatIndexInnerProductF32>()?;
+    m.add_class::<FlatIndexInnerProductU8>()?;
+    m.add_class::<FlatIndexInnerProductI8>()?;
+
+    m.add_function(wrap_pyfunction!(new_index, m)?)?;
+    m.add_function(wrap_pyfunction!(new_flat_index, m)?)?;
+
+    Ok(())
+}